@@ -0,0 +1,130 @@
+use glium::backend::glutin::Display;
+use glium::texture::Texture2d;
+use glium::VertexBuffer;
+use mesh::read_vertex_buffer;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use texture::{load_texture, reload_texture, TextureError, TextureOptions};
+use watch::FileWatcher;
+use Vertex;
+
+pub type MeshHandle = Rc<VertexBuffer<Vertex>>;
+pub type TextureHandle = Rc<Texture2d>;
+
+// What `AssetManager::poll` did with the textures whose files changed
+#[derive(Default)]
+pub struct TextureReloads {
+    // Textures whose new contents were written in place, so every handle sees them
+    pub updated: Vec<PathBuf>,
+    // Textures whose file changed size and that had to be created again. Handles from before keep
+    // the old contents until they're swapped for these, which `texture` also returns from now on
+    pub replaced: Vec<(PathBuf, TextureHandle)>,
+    // Textures that keep their previous contents because the file couldn't be loaded
+    pub failed: Vec<(PathBuf, TextureError)>,
+}
+
+struct WatchedTexture {
+    texture: Weak<Texture2d>,
+    watcher: FileWatcher,
+}
+
+// Loads each mesh and texture once and hands out reference counted handles to it. The manager
+// only keeps weak references, so an asset is freed as soon as its last handle is dropped.
+// Textures are watched and can be reloaded in place with `poll`
+pub struct AssetManager<'a> {
+    display: &'a Display,
+    meshes: HashMap<PathBuf, Weak<VertexBuffer<Vertex>>>,
+    textures: HashMap<(PathBuf, TextureOptions), WatchedTexture>,
+}
+
+impl<'a> AssetManager<'a> {
+    pub fn new(display: &'a Display) -> AssetManager<'a> {
+        AssetManager {
+            display,
+            meshes: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn mesh<P: AsRef<Path>>(&mut self, path: P) -> Result<MeshHandle, Box<Error>> {
+        let key = asset_key(path.as_ref());
+        if let Some(mesh) = self.meshes.get(&key).and_then(|weak| weak.upgrade()) {
+            return Ok(mesh);
+        }
+
+        let mesh = Rc::new(read_vertex_buffer(self.display, &key)?);
+        self.meshes.insert(key, Rc::downgrade(&mesh));
+        Ok(mesh)
+    }
+
+    pub fn texture<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: TextureOptions,
+    ) -> Result<TextureHandle, Box<Error>> {
+        let key = (asset_key(path.as_ref()), options);
+        if let Some(texture) = self.textures.get(&key).and_then(|w| w.texture.upgrade()) {
+            return Ok(texture);
+        }
+
+        let texture = Rc::new(load_texture(self.display, &key.0, options)?);
+        let watched = WatchedTexture {
+            texture: Rc::downgrade(&texture),
+            watcher: FileWatcher::new(Some(&key.0)),
+        };
+        self.textures.insert(key, watched);
+        Ok(texture)
+    }
+
+    // Number of assets that still have live handles
+    pub fn loaded(&self) -> usize {
+        let meshes = self.meshes.values().filter(|w| w.upgrade().is_some()).count();
+        let textures = self
+            .textures
+            .values()
+            .filter(|w| w.texture.upgrade().is_some())
+            .count();
+        meshes + textures
+    }
+
+    // Forgets entries whose assets have already been released
+    pub fn collect_garbage(&mut self) {
+        self.meshes.retain(|_, weak| weak.upgrade().is_some());
+        self.textures.retain(|_, watched| watched.texture.upgrade().is_some());
+    }
+
+    // Reloads every live texture whose file has changed
+    pub fn poll(&mut self) -> TextureReloads {
+        let mut reloads = TextureReloads::default();
+
+        for ((path, options), watched) in self.textures.iter_mut() {
+            let texture = match watched.texture.upgrade() {
+                Some(texture) => texture,
+                None => continue,
+            };
+            if !watched.watcher.poll() {
+                continue;
+            }
+
+            match reload_texture(self.display, &texture, path, *options) {
+                Ok(None) => reloads.updated.push(path.clone()),
+                Ok(Some(replacement)) => {
+                    let replacement = Rc::new(replacement);
+                    watched.texture = Rc::downgrade(&replacement);
+                    reloads.replaced.push((path.clone(), replacement));
+                }
+                Err(err) => reloads.failed.push((path.clone(), err)),
+            }
+        }
+
+        reloads
+    }
+}
+
+// The same file reached through different relative paths should only be loaded once
+fn asset_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,45 @@
+extern crate renderer;
+
+use renderer::mesh::{cache, read_mesh};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+// Converts a source mesh into the binary cache format
+//
+// usage: mesh_cache <input.ply|input.stl> [output.rmc] [material]
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 4 {
+        eprintln!("usage: {} <input.ply|input.stl> [output.rmc] [material]", args[0]);
+        process::exit(1);
+    }
+
+    let input = PathBuf::from(&args[1]);
+    let output = args
+        .get(2)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| input.with_extension("rmc"));
+    let material = args.get(3).map(|s| s.as_str());
+
+    let mesh = match read_mesh(&input) {
+        Ok(mesh) => mesh,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", input.display(), err);
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = cache::write_cache(&output, &mesh, material) {
+        eprintln!("failed to write {}: {}", output.display(), err);
+        process::exit(1);
+    }
+
+    println!(
+        "{} -> {} ({} vertices, {} triangles)",
+        input.display(),
+        output.display(),
+        mesh.vertices.len(),
+        mesh.triangle_count()
+    );
+}
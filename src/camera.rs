@@ -133,6 +133,38 @@ impl Projection {
     }
 }
 
+// How the light buffer is scaled before tone mapping, see `post::Tonemap`
+#[derive(Clone, Copy, Debug)]
+pub struct Exposure {
+    // Used when `auto` is off
+    pub manual: f32,
+    // Picks the exposure from the log-average luminance of the frame
+    pub auto: bool,
+    // The average luminance auto exposure maps to, middle grey by default
+    pub key: f32,
+    // Stops added to the exposure in either mode
+    pub compensation: f32,
+    // Limits on the automatic exposure
+    pub min: f32,
+    pub max: f32,
+    // How quickly auto exposure follows changes in brightness, per second
+    pub adaptation_rate: f32,
+}
+
+impl Default for Exposure {
+    fn default() -> Exposure {
+        Exposure {
+            manual: 1.0,
+            auto: false,
+            key: 0.18,
+            compensation: 0.0,
+            min: 0.01,
+            max: 100.0,
+            adaptation_rate: 1.5,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct PCamera {
     pub vertical_angle: f32,
@@ -140,6 +172,7 @@ pub struct PCamera {
     pub look_at: Pnt3,
     pub up: Unit<Vec3>,
     pub projection: Projection,
+    pub exposure: Exposure,
 }
 
 impl PCamera {
@@ -157,6 +190,7 @@ impl PCamera {
             look_at,
             up,
             projection,
+            exposure: Exposure::default(),
         }
     }
     
@@ -0,0 +1,333 @@
+// Assignment of lights to clusters for clustered lighting. The view frustum is split into a grid
+// of froxels: `x` by `y` tiles on screen and `z` slices in depth, spaced exponentially so near
+// clusters stay small. Each light is added to every cluster its bounding sphere touches.
+//
+// Everything here is plain CPU code in view space, the GPU side is `light::ClusteredLights`
+
+use camera::PCamera;
+use na::Vector4;
+use {Mat4, Vec3};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        let mut min = points[0];
+        let mut max = points[0];
+        for point in &points[1..] {
+            for i in 0..3 {
+                min[i] = min[i].min(point[i]);
+                max[i] = max[i].max(point[i]);
+            }
+        }
+        Aabb { min, max }
+    }
+
+    pub fn intersects_sphere(&self, centre: &Vec3, radius: f32) -> bool {
+        let mut distance_squared = 0.0;
+        for i in 0..3 {
+            let closest = centre[i].max(self.min[i]).min(self.max[i]);
+            distance_squared += (centre[i] - closest) * (centre[i] - closest);
+        }
+        distance_squared <= radius * radius
+    }
+}
+
+// A light as far as clustering is concerned
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightBounds {
+    // View space
+    pub centre: Vec3,
+    // None for lights that reach everywhere, which are added to every cluster
+    pub radius: Option<f32>,
+}
+
+// The lights of every cluster. The lights of cluster `i` are
+// `indices[offsets[i].0..offsets[i].0 + offsets[i].1]`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClusterLights {
+    pub offsets: Vec<(u32, u32)>,
+    pub indices: Vec<u32>,
+}
+
+impl ClusterLights {
+    pub fn lights(&self, cluster: usize) -> &[u32] {
+        let (offset, count) = self.offsets[cluster];
+        &self.indices[offset as usize..(offset + count) as usize]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClusterGrid {
+    // Tiles across, tiles up and depth slices
+    pub dimensions: [usize; 3],
+    pub near: f32,
+    pub far: f32,
+    bounds: Vec<Aabb>,
+}
+
+impl ClusterGrid {
+    pub fn new(dimensions: [usize; 3], camera: &PCamera) -> ClusterGrid {
+        ClusterGrid::from_projection(
+            dimensions,
+            &camera.projection.inverse_as_matrix(),
+            camera.znear(),
+            camera.zfar(),
+        )
+    }
+
+    // Builds the grid from an inverse projection matrix, with the view looking down -z
+    pub fn from_projection(
+        dimensions: [usize; 3],
+        inverse_projection: &Mat4,
+        near: f32,
+        far: f32,
+    ) -> ClusterGrid {
+        assert!(dimensions.iter().all(|&d| d > 0));
+        assert!(near > 0.0 && far > near);
+
+        let mut grid = ClusterGrid {
+            dimensions,
+            near,
+            far,
+            bounds: Vec::with_capacity(dimensions[0] * dimensions[1] * dimensions[2]),
+        };
+
+        let unproject = |x: f32, y: f32, z: f32| {
+            let point = inverse_projection * Vector4::new(x, y, z, 1.0);
+            Vec3::new(point.x, point.y, point.z) / point.w
+        };
+
+        for k in 0..dimensions[2] {
+            let slice_near = grid.slice_depth(k);
+            let slice_far = grid.slice_depth(k + 1);
+
+            for j in 0..dimensions[1] {
+                for i in 0..dimensions[0] {
+                    let mut corners = Vec::with_capacity(8);
+                    for &(x, y) in &[(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)] {
+                        let ndc_x = x as f32 / dimensions[0] as f32 * 2.0 - 1.0;
+                        let ndc_y = y as f32 / dimensions[1] as f32 * 2.0 - 1.0;
+                        // The corner's edge of the frustum, from the near to the far plane
+                        let on_near = unproject(ndc_x, ndc_y, -1.0);
+                        let on_far = unproject(ndc_x, ndc_y, 1.0);
+                        for &depth in &[slice_near, slice_far] {
+                            let t = (depth - near) / (far - near);
+                            corners.push(on_near + (on_far - on_near) * t);
+                        }
+                    }
+                    grid.bounds.push(Aabb::from_points(&corners));
+                }
+            }
+        }
+
+        grid
+    }
+
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + y * self.dimensions[0] + z * self.dimensions[0] * self.dimensions[1]
+    }
+
+    pub fn bounds(&self, index: usize) -> &Aabb {
+        &self.bounds[index]
+    }
+
+    // View depth, positive into the screen, where slice `k` starts
+    pub fn slice_depth(&self, k: usize) -> f32 {
+        let fraction = k as f32 / self.dimensions[2] as f32;
+        self.near * (self.far / self.near).powf(fraction)
+    }
+
+    // The slice holding a view depth, clamped to the grid
+    pub fn slice(&self, depth: f32) -> usize {
+        if depth <= self.near {
+            return 0;
+        }
+        let fraction = (depth / self.near).ln() / (self.far / self.near).ln();
+        let slice = (fraction * self.dimensions[2] as f32) as usize;
+        slice.min(self.dimensions[2] - 1)
+    }
+
+    pub fn assign(&self, lights: &[LightBounds]) -> ClusterLights {
+        let mut clusters: Vec<Vec<u32>> = vec![Vec::new(); self.len()];
+        let slice_len = self.dimensions[0] * self.dimensions[1];
+
+        for (light_index, light) in lights.iter().enumerate() {
+            // Only the slices the sphere spans in depth need to be tested
+            let (first, last) = match light.radius {
+                Some(radius) => {
+                    let depth = -light.centre.z;
+                    if depth + radius < self.near || depth - radius > self.far {
+                        continue;
+                    }
+                    (self.slice(depth - radius), self.slice(depth + radius))
+                }
+                None => (0, self.dimensions[2] - 1),
+            };
+
+            for cluster in first * slice_len..(last + 1) * slice_len {
+                let touches = match light.radius {
+                    Some(radius) => self.bounds[cluster].intersects_sphere(&light.centre, radius),
+                    None => true,
+                };
+                if touches {
+                    clusters[cluster].push(light_index as u32);
+                }
+            }
+        }
+
+        let mut assignment = ClusterLights {
+            offsets: Vec::with_capacity(clusters.len()),
+            indices: Vec::new(),
+        };
+        for lights in clusters {
+            let offset = assignment.indices.len() as u32;
+            assignment.offsets.push((offset, lights.len() as u32));
+            assignment.indices.extend(lights);
+        }
+        assignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::Perspective3;
+    use std::f32::consts::PI;
+
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    // 4 by 4 tiles and 16 slices over a square 90 degree frustum
+    fn grid() -> ClusterGrid {
+        let projection = Perspective3::new(1.0, PI / 2.0, NEAR, FAR);
+        ClusterGrid::from_projection([4, 4, 16], &projection.inverse(), NEAR, FAR)
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> LightBounds {
+        LightBounds {
+            centre: Vec3::new(x, y, z),
+            radius: Some(radius),
+        }
+    }
+
+    #[test]
+    fn slices_round_trip() {
+        let grid = grid();
+        assert!((grid.slice_depth(0) - NEAR).abs() < 1e-6);
+        assert!((grid.slice_depth(16) - FAR).abs() < 1e-3);
+
+        assert_eq!(grid.slice(NEAR), 0);
+        assert_eq!(grid.slice(FAR), 15);
+        // Depths outside the grid are clamped
+        assert_eq!(grid.slice(NEAR * 0.5), 0);
+        assert_eq!(grid.slice(FAR * 2.0), 15);
+
+        // The geometric middle of each slice
+        for k in 0..16 {
+            let middle = (grid.slice_depth(k) * grid.slice_depth(k + 1)).sqrt();
+            assert_eq!(grid.slice(middle), k, "depth {}", middle);
+        }
+    }
+
+    #[test]
+    fn sphere_is_added_to_the_clusters_it_touches() {
+        let grid = grid();
+        let light = sphere(0.0, 0.0, -5.0, 1.0);
+        let assignment = grid.assign(&[light]);
+
+        let mut slices = Vec::new();
+        let mut tiles = Vec::new();
+        for z in 0..16 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    let cluster = grid.index(x, y, z);
+                    let touches = grid.bounds(cluster).intersects_sphere(&light.centre, 1.0);
+                    let assigned = assignment.lights(cluster) == &[0];
+                    assert_eq!(assigned, touches, "cluster {:?}", (x, y, z));
+                    assert!(assigned || assignment.lights(cluster).is_empty());
+                    if assigned {
+                        slices.push(z);
+                        tiles.push((x, y));
+                    }
+                }
+            }
+        }
+
+        // The sphere spans depths 4 to 6 around the centre of the screen
+        slices.dedup();
+        assert_eq!(slices, (grid.slice(4.0)..grid.slice(6.0) + 1).collect::<Vec<_>>());
+        assert!(slices.len() > 1);
+        for tile in &[(1, 1), (2, 1), (1, 2), (2, 2)] {
+            assert!(tiles.contains(tile), "tile {:?}", tile);
+        }
+        assert!(!tiles.contains(&(0, 0)));
+    }
+
+    #[test]
+    fn lights_outside_the_depth_range_are_not_added() {
+        let grid = grid();
+        let lights = [
+            // Behind the camera
+            sphere(0.0, 0.0, 5.0, 1.0),
+            // Between the camera and the near plane
+            sphere(0.0, 0.0, -0.02, 0.05),
+            // Beyond the far plane
+            sphere(0.0, 0.0, -FAR - 2.0, 1.0),
+        ];
+        let assignment = grid.assign(&lights);
+        assert!(assignment.indices.is_empty());
+        assert!(assignment.offsets.iter().all(|&(_, count)| count == 0));
+    }
+
+    #[test]
+    fn lights_without_a_radius_are_added_everywhere() {
+        let grid = grid();
+        let directional = LightBounds {
+            centre: Vec3::new(0.0, 0.0, 0.0),
+            radius: None,
+        };
+        let assignment = grid.assign(&[directional]);
+        assert_eq!(assignment.offsets.len(), grid.len());
+        for cluster in 0..grid.len() {
+            assert_eq!(assignment.lights(cluster), &[0]);
+        }
+    }
+
+    #[test]
+    fn offsets_index_packed_lists() {
+        let grid = grid();
+        let lights = [
+            sphere(0.0, 0.0, -5.0, 1.0),
+            LightBounds {
+                centre: Vec3::new(0.0, 0.0, 0.0),
+                radius: None,
+            },
+            sphere(-3.0, 2.0, -10.0, 2.0),
+            sphere(0.0, 0.0, -FAR - 2.0, 1.0),
+        ];
+        let assignment = grid.assign(&lights);
+        assert_eq!(assignment.offsets.len(), grid.len());
+
+        // Every cluster's list starts where the previous one ends, in light order
+        let mut next = 0;
+        for (cluster, &(offset, count)) in assignment.offsets.iter().enumerate() {
+            assert_eq!(offset, next, "cluster {}", cluster);
+            next += count;
+
+            let lights = assignment.lights(cluster);
+            assert!(lights.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(lights.contains(&1));
+            assert!(!lights.contains(&3));
+        }
+        assert_eq!(next as usize, assignment.indices.len());
+    }
+}
@@ -0,0 +1,104 @@
+use camera::{PCamera, Projection};
+use gbuffer::GBuffer;
+use glium::backend::glutin::Display;
+use glium::index::{NoIndices, PrimitiveType::TrianglesList};
+use glium::vertex::EmptyVertexAttributes;
+use glium::{Program, Surface};
+use program::debug_program;
+use std::error::Error;
+
+// What `DebugPass` shows. The channels are read from the "diffuse", "normal" and "specular"
+// attachments of the default layout, with "material" standing in for "specular" in the
+// metallic-roughness layout. A layout without one of them shows the light buffer instead
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    Albedo,
+    Normals,
+    Specular,
+    // Linear depth, black at the near end of the depth range and white at the far end
+    Depth,
+    // Reconstructed world position, with -range..range mapped to 0..1 on each axis
+    Position,
+    Light,
+    // All of the above in a 3 by 2 grid
+    Grid,
+}
+
+impl DebugView {
+    // The `view` uniform of the debug shader
+    fn index(&self) -> i32 {
+        match self {
+            DebugView::Albedo => 0,
+            DebugView::Normals => 1,
+            DebugView::Specular => 2,
+            DebugView::Depth => 3,
+            DebugView::Position => 4,
+            DebugView::Light => 5,
+            DebugView::Grid => 6,
+        }
+    }
+}
+
+// Draws a G-buffer channel over the whole of a surface with a full-screen triangle
+pub struct DebugPass {
+    program: Program,
+    pub view: DebugView,
+    // Range shown by `DebugView::Depth`. Uses the camera's near and far planes if not set
+    pub depth_range: Option<(f32, f32)>,
+    pub position_range: f32,
+}
+
+impl DebugPass {
+    pub fn new(display: &Display) -> Result<DebugPass, Box<Error>> {
+        Ok(DebugPass {
+            program: debug_program(display)?,
+            view: DebugView::Grid,
+            depth_range: None,
+            position_range: 10.0,
+        })
+    }
+
+    pub fn draw<S: Surface>(
+        &self,
+        surface: &mut S,
+        gbuffer: &GBuffer,
+        camera: &PCamera,
+    ) -> Result<(), Box<Error>> {
+        let projection = camera.projection_matrix();
+        let orthographic = match camera.projection {
+            Projection::Orthographic(_) => true,
+            Projection::Perspective(_) => false,
+        };
+        let (depth_near, depth_far) = self
+            .depth_range
+            .unwrap_or_else(|| (camera.znear(), camera.zfar()));
+
+        let uniforms = uniform! {
+            diffuse_tex: gbuffer.attachment("diffuse").unwrap_or(&gbuffer.light),
+            normal_tex: gbuffer.attachment("normal").unwrap_or(&gbuffer.light),
+            specular_tex: gbuffer
+                .attachment("specular")
+                .or_else(|| gbuffer.attachment("material"))
+                .unwrap_or(&gbuffer.light),
+            depth_tex: &gbuffer.depth,
+            light_tex: &gbuffer.light,
+            view: self.view.index(),
+            depth_near: depth_near,
+            depth_far: depth_far,
+            position_range: self.position_range,
+            T1: projection[(2, 2)],
+            T2: projection[(2, 3)],
+            orthographic: orthographic,
+            inv_projection: *camera.inv_view_matrix().as_ref(),
+        };
+
+        surface.draw(
+            EmptyVertexAttributes { len: 3 },
+            NoIndices(TrianglesList),
+            &self.program,
+            &uniforms,
+            &Default::default(),
+        )?;
+        Ok(())
+    }
+}
@@ -1,29 +1,135 @@
-use camera::PCamera;
+use camera::{PCamera, Projection};
 use glium::{
-    backend::glutin::Display, draw_parameters::DrawParameters,
+    backend::glutin::Display,
+    draw_parameters::{
+        BackfaceCullingMode, Blend, BlendingFunction, Depth, DepthClamp, DepthTest,
+        DrawParameters, LinearBlendingFactor, Stencil, StencilOperation, StencilTest,
+    },
     framebuffer::{MultiOutputFrameBuffer, SimpleFrameBuffer},
     index::{IndicesSource, NoIndices, PrimitiveType::TrianglesList},
     texture::{
-        DepthFormat::F32, DepthTexture2d, MipmapsOption::NoMipmap, Texture2d,
-        UncompressedFloatFormat::F32F32F32F32,
+        DepthFormat, DepthStencilFormat, DepthStencilTexture2d, DepthTexture2d,
+        MipmapsOption::NoMipmap, Texture2d, UncompressedFloatFormat,
     },
-    uniforms::Uniforms, vertex::Vertex, Program, Surface, VertexBuffer,
+    uniforms::{EmptyUniforms, UniformValue, Uniforms}, vertex::{EmptyVertexAttributes, Vertex}, Program,
+    Surface, VertexBuffer,
 };
-use render_object::{LightModel, ModelMatrix, PosMatrix, RenderObject};
+use material::{Material, MaterialModel, UniformsChain};
+use math::oct_decode;
+use program::{depth_copy_program, linear_depth_program};
+use readback::{read_texture, FloatImage};
+use ibl::Environment;
+use light::{ClusteredLights, Light, LightVolume, LightVolumes};
+use render_object::{ModelMatrix, RenderObject};
+use shadow::ShadowMap;
+use sky::SkyPass;
+use ssao::Ssao;
 use std::error::Error;
+use {Mat4, Vec2};
+
+// Uniforms `PrePass::draw_object` sets itself
+pub const OBJECT_UNIFORMS: &[&str] = &[
+    "view",
+    "model",
+    "normal_map",
+    "depth_map",
+    "diffuse_map",
+    "specular_map",
+    "depth_scale",
+    "roughness",
+    "metallic",
+    "eye",
+];
+
+#[derive(Clone, Debug)]
+pub struct AttachmentDesc {
+    // The prepass fragment output it is bound to. The lighting pass samples it as `<name>_tex`
+    pub name: String,
+    pub format: UncompressedFloatFormat,
+}
 
-pub enum RenderStage {
-    PrePass,
-    Lighting,
+// The colour attachments written by the prepass along with the depth and light buffer formats
+#[derive(Clone, Debug)]
+pub struct GBufferLayout {
+    pub attachments: Vec<AttachmentDesc>,
+    pub depth: DepthFormat,
+    pub light: UncompressedFloatFormat,
+    // Depth-stencil target of the lighting pass, see `GBuffer::light_depth`
+    pub light_depth: DepthStencilFormat,
+}
+
+impl GBufferLayout {
+    pub fn new(depth: DepthFormat, light: UncompressedFloatFormat) -> GBufferLayout {
+        GBufferLayout {
+            attachments: Vec::new(),
+            depth,
+            light,
+            light_depth: DepthStencilFormat::I24I8,
+        }
+    }
+
+    pub fn with_attachment(mut self, name: &str, format: UncompressedFloatFormat) -> GBufferLayout {
+        self.attachments.push(AttachmentDesc {
+            name: name.to_string(),
+            format,
+        });
+        self
+    }
+
+    // 8 bit albedo and specular, 16 bit octahedral normals and a packed float light buffer.
+    // 20 bytes per pixel with depth instead of 56 for the default layout
+    pub fn compact() -> GBufferLayout {
+        GBufferLayout::new(DepthFormat::F32, UncompressedFloatFormat::F11F11F10)
+            .with_attachment("diffuse", UncompressedFloatFormat::U8U8U8U8)
+            .with_attachment("normal", UncompressedFloatFormat::U16U16)
+            .with_attachment("specular", UncompressedFloatFormat::U8U8U8U8)
+    }
+
+    // The layout of `MaterialModel::MetallicRoughness`. "diffuse" holds the albedo and
+    // "material" the metallic, roughness and ambient occlusion
+    pub fn metallic_roughness() -> GBufferLayout {
+        GBufferLayout::new(DepthFormat::F32, UncompressedFloatFormat::F32F32F32F32)
+            .with_attachment("diffuse", UncompressedFloatFormat::U8U8U8U8)
+            .with_attachment("normal", UncompressedFloatFormat::U16U16)
+            .with_attachment("material", UncompressedFloatFormat::U8U8U8U8)
+    }
+
+    pub fn for_model(model: MaterialModel) -> GBufferLayout {
+        match model {
+            MaterialModel::BlinnPhong => GBufferLayout::default(),
+            MaterialModel::MetallicRoughness => GBufferLayout::metallic_roughness(),
+        }
+    }
+}
+
+// RGBA32F diffuse and specular with two channel normals, matching the shipped shaders
+impl Default for GBufferLayout {
+    fn default() -> GBufferLayout {
+        GBufferLayout::new(DepthFormat::F32, UncompressedFloatFormat::F32F32F32F32)
+            .with_attachment("diffuse", UncompressedFloatFormat::F32F32F32F32)
+            .with_attachment("normal", UncompressedFloatFormat::U16U16)
+            .with_attachment("specular", UncompressedFloatFormat::F32F32F32F32)
+    }
+}
+
+pub struct GBufferAttachment {
+    pub name: String,
+    pub sampler: String,
+    pub texture: Texture2d,
 }
 
 pub struct GBuffer<'a> {
     display: &'a Display,
-    pub diffuse: Texture2d,
-    pub normal: Texture2d,
-    pub specular: Texture2d,
+    layout: GBufferLayout,
+    pub attachments: Vec<GBufferAttachment>,
     pub depth: DepthTexture2d,
     pub light: Texture2d,
+    // The lighting pass tests light volumes against a copy of `depth` and marks them in the
+    // stencil buffer here. glium can't sample a depth-stencil texture, so `depth` itself stays a
+    // plain depth texture for the shaders to read
+    pub light_depth: DepthStencilTexture2d,
+    // Compiled once for `read_linear_depth`, which may be called every frame
+    linear_depth_program: Program,
 }
 
 impl<'a> GBuffer<'a> {
@@ -31,95 +137,227 @@ impl<'a> GBuffer<'a> {
         display: &Display,
         dimensions: (u32, u32),
     ) -> Result<GBuffer, Box<Error>> {
-        let diffuse = Texture2d::empty_with_format(
-            display,
-            F32F32F32F32,
-            NoMipmap,
-            dimensions.0,
-            dimensions.1,
-        )?;
-        let normal = Texture2d::empty_with_format(
-            display,
-            F32F32F32F32,
-            NoMipmap,
-            dimensions.0,
-            dimensions.1,
-        )?;
-        let specular = Texture2d::empty_with_format(
-            display,
-            F32F32F32F32,
-            NoMipmap,
-            dimensions.0,
-            dimensions.1,
-        )?;
-        let depth =
-            DepthTexture2d::empty_with_format(display, F32, NoMipmap, dimensions.0, dimensions.1)?;
-        let light = Texture2d::empty_with_format(
-            display,
-            F32F32F32F32,
-            NoMipmap,
-            dimensions.0,
-            dimensions.1,
-        )?;
+        GBuffer::with_layout(display, GBufferLayout::default(), dimensions)
+    }
 
+    pub fn with_layout(
+        display: &Display,
+        layout: GBufferLayout,
+        dimensions: (u32, u32),
+    ) -> Result<GBuffer, Box<Error>> {
+        let (attachments, depth, light, light_depth) = targets(display, &layout, dimensions)?;
         Ok(GBuffer {
             display,
-            diffuse,
-            normal,
-            specular,
+            layout,
+            attachments,
             depth,
             light,
+            light_depth,
+            linear_depth_program: linear_depth_program(display)?,
         })
     }
 
+    pub fn layout(&self) -> &GBufferLayout {
+        &self.layout
+    }
+
+    pub fn attachment(&self, name: &str) -> Option<&Texture2d> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.name == name)
+            .map(|attachment| &attachment.texture)
+    }
+
+    // Every attachment as `<name>_tex` plus the depth buffer as `depth_tex`
+    pub fn textures(&self) -> GBufferTextures {
+        GBufferTextures(self)
+    }
+
     pub fn resize(&mut self, dimensions: (u32, u32)) -> Result<(), Box<Error>> {
-        *self = GBuffer::with_dimensions(self.display, dimensions)?;
+        let (attachments, depth, light, light_depth) = targets(self.display, &self.layout, dimensions)?;
+        self.attachments = attachments;
+        self.depth = depth;
+        self.light = light;
+        self.light_depth = light_depth;
         Ok(())
     }
 
     pub fn framebuffer(&self) -> Result<MultiOutputFrameBuffer, Box<Error>> {
-        let output = &[
-            ("diffuse", &self.diffuse),
-            ("normal", &self.normal),
-            ("specular", &self.specular),
-        ];
+        let output = self
+            .attachments
+            .iter()
+            .map(|attachment| (attachment.name.as_str(), &attachment.texture));
         let framebuffer = MultiOutputFrameBuffer::with_depth_buffer(
             *&self.display,
-            output.iter().cloned(),
+            output,
             &self.depth,
         )?;
         Ok(framebuffer)
     }
 
     pub fn lightbuffer(&self) -> Result<SimpleFrameBuffer, Box<Error>> {
-        let lightbuffer =
-            SimpleFrameBuffer::with_depth_buffer(*&self.display, &self.light, &self.depth)?;
+        let lightbuffer = SimpleFrameBuffer::with_depth_stencil_buffer(
+            *&self.display,
+            &self.light,
+            &self.light_depth,
+        )?;
         Ok(lightbuffer)
     }
+
+    // Copies an attachment back to the CPU as stored, so normals are still encoded
+    pub fn read_attachment(&self, name: &str) -> Option<FloatImage> {
+        self.attachment(name).map(read_texture)
+    }
+
+    // World space normals from the "normal" attachment, mapped to 0..1 so they can be viewed
+    pub fn read_normals(&self) -> Option<FloatImage> {
+        let encoded = self.read_attachment("normal")?;
+        let mut image = FloatImage::new(encoded.width, encoded.height, 3);
+        for (src, dst) in encoded.data.chunks(4).zip(image.data.chunks_mut(3)) {
+            let normal = oct_decode(&Vec2::new(src[0], src[1]));
+            for i in 0..3 {
+                dst[i] = normal[i] * 0.5 + 0.5;
+            }
+        }
+        Some(image)
+    }
+
+    pub fn read_light(&self) -> FloatImage {
+        read_texture(&self.light)
+    }
+
+    // The distance of each pixel from the camera plane in world units, single channel, as drawn
+    // by the last prepass. The depth texture can't be read directly, so it is converted in a
+    // full-screen pass first
+    pub fn read_linear_depth(&self, camera: &PCamera) -> Result<FloatImage, Box<Error>> {
+        let (width, height) = self.depth.dimensions();
+        let target = Texture2d::empty_with_format(
+            self.display,
+            UncompressedFloatFormat::F32,
+            NoMipmap,
+            width,
+            height,
+        )?;
+
+        let projection = camera.projection_matrix();
+        let orthographic = match camera.projection {
+            Projection::Orthographic(_) => true,
+            Projection::Perspective(_) => false,
+        };
+        let uniforms = uniform! {
+            depth_tex: &self.depth,
+            T1: projection[(2, 2)],
+            T2: projection[(2, 3)],
+            orthographic: orthographic,
+        };
+
+        let mut framebuffer = SimpleFrameBuffer::new(self.display, &target)?;
+        framebuffer.draw(
+            EmptyVertexAttributes { len: 3 },
+            NoIndices(TrianglesList),
+            &self.linear_depth_program,
+            &uniforms,
+            &Default::default(),
+        )?;
+
+        Ok(read_texture(&target).with_channels(1))
+    }
+}
+
+// The attachments, depth, light buffer and light depth-stencil target of `layout`
+fn targets(
+    display: &Display,
+    layout: &GBufferLayout,
+    dimensions: (u32, u32),
+) -> Result<(Vec<GBufferAttachment>, DepthTexture2d, Texture2d, DepthStencilTexture2d), Box<Error>> {
+    let mut attachments = Vec::with_capacity(layout.attachments.len());
+    for desc in &layout.attachments {
+        let texture = Texture2d::empty_with_format(
+            display,
+            desc.format,
+            NoMipmap,
+            dimensions.0,
+            dimensions.1,
+        )?;
+        attachments.push(GBufferAttachment {
+            name: desc.name.clone(),
+            sampler: format!("{}_tex", desc.name),
+            texture,
+        });
+    }
+    let depth = DepthTexture2d::empty_with_format(
+        display,
+        layout.depth,
+        NoMipmap,
+        dimensions.0,
+        dimensions.1,
+    )?;
+    let light = Texture2d::empty_with_format(
+        display,
+        layout.light,
+        NoMipmap,
+        dimensions.0,
+        dimensions.1,
+    )?;
+    let light_depth = DepthStencilTexture2d::empty_with_format(
+        display,
+        layout.light_depth,
+        NoMipmap,
+        dimensions.0,
+        dimensions.1,
+    )?;
+    Ok((attachments, depth, light, light_depth))
 }
 
+pub struct GBufferTextures<'g>(&'g GBuffer<'g>);
+
+impl<'g> Uniforms for GBufferTextures<'g> {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        for attachment in &self.0.attachments {
+            output(&attachment.sampler, UniformValue::Texture2d(&attachment.texture, None));
+        }
+        output("depth_tex", UniformValue::DepthTexture2d(&self.0.depth, None));
+    }
+}
+
+// The render targets of a `GBuffer`. A frame is drawn through the passes it hands out: the
+// prepass draws objects into the G-buffer, then the lighting pass draws lights into the light
+// buffer. Each pass can only be started from the one before it, so nothing can be drawn into
+// the wrong target. tests/gbuffer.rs draws a frame this way
 pub struct FrameBuffers<'a> {
-    pub framebuffer: MultiOutputFrameBuffer<'a>,
-    pub lightbuffer: SimpleFrameBuffer<'a>,
-    stage: RenderStage,
+    gbuffer: &'a GBuffer<'a>,
+    framebuffer: MultiOutputFrameBuffer<'a>,
+    lightbuffer: SimpleFrameBuffer<'a>,
+    // Copies the G-buffer depth into the lighting pass's depth-stencil target
+    depth_copy_program: Program,
 }
 
 impl<'a> FrameBuffers<'a> {
     pub fn new(gbuffer: &'a GBuffer<'a>) -> Result<FrameBuffers<'a>, Box<Error>> {
         Ok(FrameBuffers {
+            gbuffer,
             framebuffer: gbuffer.framebuffer()?,
             lightbuffer: gbuffer.lightbuffer()?,
-            stage: RenderStage::PrePass,
+            depth_copy_program: depth_copy_program(gbuffer.display)?,
         })
     }
 
-    pub fn reset(&mut self) {
+    // Clears every target and starts the frame
+    pub fn begin_prepass<'f>(&'f mut self) -> PrePass<'f, 'a> {
         self.framebuffer
             .clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
         self.lightbuffer.clear_color(0.0, 0.0, 0.0, 0.0);
-        self.stage = RenderStage::PrePass;
+        PrePass { buffers: self }
     }
+}
+
+// Draws into the attachments of the G-buffer layout and the depth target. It has no way to
+// draw lights, those wait for `begin_lighting`
+pub struct PrePass<'f, 'a: 'f> {
+    buffers: &'f mut FrameBuffers<'a>,
+}
 
+impl<'f, 'a> PrePass<'f, 'a> {
     pub fn draw<'b, V, I, U>(
         &mut self,
         buffer: &VertexBuffer<V>,
@@ -133,16 +371,9 @@ impl<'a> FrameBuffers<'a> {
         I: Into<IndicesSource<'b>>,
         U: Uniforms,
     {
-        match self.stage {
-            RenderStage::PrePass => {
-                self.framebuffer
-                    .draw(buffer, indices, program, uniforms, draw_parameters)?;
-            }
-            RenderStage::Lighting => {
-                self.lightbuffer
-                    .draw(buffer, indices, program, uniforms, draw_parameters)?;
-            }
-        }
+        self.buffers
+            .framebuffer
+            .draw(buffer, indices, program, uniforms, draw_parameters)?;
         Ok(())
     }
 
@@ -161,6 +392,8 @@ impl<'a> FrameBuffers<'a> {
             diffuse_map: render_object.diffuse_tex,
             specular_map: render_object.specular_tex,
             depth_scale: render_object.depth_scale,
+            roughness: render_object.roughness,
+            metallic: render_object.metallic,
             eye: *camera.position.coords.as_ref(),
         };
 
@@ -174,44 +407,372 @@ impl<'a> FrameBuffers<'a> {
         Ok(())
     }
 
-    pub fn draw_light<T: PosMatrix>(
+    // Same as `draw_object` but with the material's program and params, so custom shaders can
+    // take their own parameters. Create the material with `OBJECT_UNIFORMS` as the provided
+    // uniforms
+    pub fn draw_object_with<T: ModelMatrix>(
+        &mut self,
+        render_object: &RenderObject<T>,
+        material: &Material,
+        camera: &PCamera,
+        draw_parameters: &DrawParameters,
+    ) -> Result<(), Box<Error>> {
+        let uniforms = uniform! {
+            view: *camera.view_matrix().as_ref(),
+            model: render_object.model_matrix.matrix(),
+            normal_map: render_object.normal_tex,
+            depth_map: render_object.depth_tex,
+            diffuse_map: render_object.diffuse_tex,
+            specular_map: render_object.specular_tex,
+            depth_scale: render_object.depth_scale,
+            roughness: render_object.roughness,
+            metallic: render_object.metallic,
+            eye: *camera.position.coords.as_ref(),
+        };
+
+        self.draw(
+            render_object.buffer,
+            NoIndices(TrianglesList),
+            material.program(),
+            &UniformsChain(&uniforms, material),
+            draw_parameters,
+        )?;
+        Ok(())
+    }
+
+    // Ends the prepass. The G-buffer can only be read from here on. Copies the G-buffer depth
+    // into the light pass's depth-stencil target and clears its stencil
+    pub fn begin_lighting(self) -> Result<LightingPass<'f, 'a>, Box<Error>> {
+        let buffers = self.buffers;
+        buffers.lightbuffer.clear_stencil(0);
+
+        let uniforms = uniform! {
+            depth_tex: &buffers.gbuffer.depth,
+        };
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::Overwrite,
+                write: true,
+                ..Default::default()
+            },
+            color_mask: (false, false, false, false),
+            ..Default::default()
+        };
+        buffers.lightbuffer.draw(
+            EmptyVertexAttributes { len: 3 },
+            NoIndices(TrianglesList),
+            &buffers.depth_copy_program,
+            &uniforms,
+            &draw_parameters,
+        )?;
+
+        Ok(LightingPass { buffers })
+    }
+}
+
+// Draws into the light buffer, reading from the G-buffer. Objects can only be drawn during the
+// prepass
+pub struct LightingPass<'f, 'a: 'f> {
+    buffers: &'f mut FrameBuffers<'a>,
+}
+
+impl<'f, 'a> LightingPass<'f, 'a> {
+    pub fn draw<'b, V, I, U>(
+        &mut self,
+        buffer: &VertexBuffer<V>,
+        indices: I,
+        program: &Program,
+        uniforms: &U,
+        draw_parameters: &DrawParameters,
+    ) -> Result<(), Box<Error>>
+    where
+        V: Vertex,
+        I: Into<IndicesSource<'b>>,
+        U: Uniforms,
+    {
+        self.buffers
+            .lightbuffer
+            .draw(buffer, indices, program, uniforms, draw_parameters)?;
+        Ok(())
+    }
+
+    // Shades the pixels inside the light's volume from `volumes`, or the whole screen for a
+    // directional light. Volumes are drawn twice: first into the stencil buffer to find the
+    // pixels whose surface lies inside the volume, then with `program` on those pixels only.
+    // This also works with the camera inside the volume. The depth, stencil, culling and
+    // blending of `draw_parameters` are replaced, everything else is kept. `shininess` is only
+    // used by the Blinn-Phong material model
+    pub fn draw_light(
+        &mut self,
+        shininess: f32,
+        light: &Light,
+        volumes: &LightVolumes,
+        camera: &PCamera,
+        program: &Program,
+        draw_parameters: &DrawParameters,
+    ) -> Result<(), Box<Error>> {
+        self.draw_light_with(shininess, light, &EmptyUniforms, volumes, camera, program, draw_parameters)
+    }
+
+    // Same as `draw_light` with shadows from `shadow_map`, which must have been rendered from
+    // `light`. `program` needs the shadow defines from `lighting_defines`
+    pub fn draw_shadowed_light(
         &mut self,
         shininess: f32,
-        light: &LightModel<T>,
+        light: &Light,
+        shadow_map: &ShadowMap,
+        volumes: &LightVolumes,
+        camera: &PCamera,
+        program: &Program,
+        draw_parameters: &DrawParameters,
+    ) -> Result<(), Box<Error>> {
+        self.draw_light_with(shininess, light, shadow_map, volumes, camera, program, draw_parameters)
+    }
+
+    fn draw_light_with<U: Uniforms>(
+        &mut self,
+        shininess: f32,
+        light: &Light,
+        extra: &U,
+        volumes: &LightVolumes,
         camera: &PCamera,
         program: &Program,
         draw_parameters: &DrawParameters,
-        gbuffer: &GBuffer,
     ) -> Result<(), Box<Error>> {
         let perspective_mat = camera.projection_matrix();
         let look_at_mat = camera.look_at_matrix();
+        let volume = light.volume();
+        // The full-screen triangle is already in clip space
+        let view = match volume {
+            LightVolume::FullScreen => Mat4::identity(),
+            _ => perspective_mat * look_at_mat,
+        };
         let uniforms = uniform! {
-            view: *(perspective_mat * look_at_mat).as_ref(),
-            model: light.position.matrix(),
-            normal_tex: &gbuffer.normal,
-            depth_tex: &gbuffer.depth,
-            diffuse_tex: &gbuffer.diffuse,
-            specular_tex: &gbuffer.specular,
+            view: *view.as_ref(),
+            model: *light.volume_matrix().as_ref(),
             eye: *camera.position.coords.as_ref(),
             inv_projection: *camera.inv_view_matrix().as_ref(),
             shininess: shininess,
             T1: perspective_mat[(2, 2)],
             T2: perspective_mat[(2, 3)],
-            light_pos: light.position.position(),
-            light_colour: light.colour,
         };
 
+        // Volumes clipped by the far plane would leave holes, so depth is clamped instead
+        let no_depth_test = Depth {
+            test: DepthTest::Overwrite,
+            write: false,
+            clamp: DepthClamp::Clamp,
+            ..Default::default()
+        };
+
+        if volume != LightVolume::FullScreen {
+            // Counts, per pixel, back faces behind the surface minus front faces behind it.
+            // Only pixels whose surface is inside the volume end up non-zero. Front faces are
+            // counter-clockwise on screen
+            let stencil_parameters = DrawParameters {
+                depth: Depth {
+                    test: DepthTest::IfLess,
+                    ..no_depth_test
+                },
+                stencil: Stencil {
+                    pass_depth_fail_operation_clockwise: StencilOperation::IncrementWrap,
+                    pass_depth_fail_operation_counter_clockwise: StencilOperation::DecrementWrap,
+                    ..Default::default()
+                },
+                backface_culling: BackfaceCullingMode::CullingDisabled,
+                color_mask: (false, false, false, false),
+                ..draw_parameters.clone()
+            };
+            self.draw(
+                volumes.get(volume),
+                NoIndices(TrianglesList),
+                &volumes.stencil_program,
+                &uniforms,
+                &stencil_parameters,
+            )?;
+        }
+
+        // Lights are summed in the HDR light buffer. Back faces are drawn so the light still
+        // shows with the camera inside the volume, and the stencil is reset to zero for the
+        // next light as it is read
+        let inside = StencilTest::IfNotEqual { mask: 0xff };
+        let light_parameters = DrawParameters {
+            blend: additive_blend(),
+            depth: no_depth_test,
+            stencil: match volume {
+                LightVolume::FullScreen => Stencil::default(),
+                _ => Stencil {
+                    test_clockwise: inside,
+                    test_counter_clockwise: inside,
+                    reference_value_clockwise: 0,
+                    reference_value_counter_clockwise: 0,
+                    depth_pass_operation_clockwise: StencilOperation::Zero,
+                    depth_pass_operation_counter_clockwise: StencilOperation::Zero,
+                    ..Default::default()
+                },
+            },
+            backface_culling: match volume {
+                LightVolume::FullScreen => BackfaceCullingMode::CullingDisabled,
+                _ => BackfaceCullingMode::CullCounterClockwise,
+            },
+            ..draw_parameters.clone()
+        };
+
+        let gbuffer = self.buffers.gbuffer;
         self.draw(
-            &light.buffer,
+            volumes.get(volume),
             NoIndices(TrianglesList),
             program,
-            &uniforms,
-            draw_parameters,
+            &UniformsChain(
+                &UniformsChain(&uniforms, light),
+                &UniformsChain(extra, &gbuffer.textures()),
+            ),
+            &light_parameters,
+        )?;
+        Ok(())
+    }
+
+    // Adds the ambient light of `environment` to every pixel with geometry. `program` comes
+    // from `ambient_program` with the G-buffer's material model, and with occlusion if and only
+    // if `occlusion` is given. The depth, stencil and blending of `draw_parameters` are replaced
+    pub fn draw_ambient(
+        &mut self,
+        shininess: f32,
+        environment: &Environment,
+        occlusion: Option<&Ssao>,
+        camera: &PCamera,
+        program: &Program,
+        draw_parameters: &DrawParameters,
+    ) -> Result<(), Box<Error>> {
+        let perspective_mat = camera.projection_matrix();
+        let uniforms = uniform! {
+            eye: *camera.position.coords.as_ref(),
+            inv_projection: *camera.inv_view_matrix().as_ref(),
+            shininess: shininess,
+            T1: perspective_mat[(2, 2)],
+            T2: perspective_mat[(2, 3)],
+        };
+        let parameters = DrawParameters {
+            blend: additive_blend(),
+            depth: Depth {
+                test: DepthTest::Overwrite,
+                write: false,
+                ..Default::default()
+            },
+            stencil: Stencil::default(),
+            ..draw_parameters.clone()
+        };
+
+        let gbuffer = self.buffers.gbuffer;
+        let uniforms = UniformsChain(&uniforms, environment);
+        match occlusion {
+            Some(ssao) => self.buffers.lightbuffer.draw(
+                EmptyVertexAttributes { len: 3 },
+                NoIndices(TrianglesList),
+                program,
+                &UniformsChain(&UniformsChain(&uniforms, ssao), &gbuffer.textures()),
+                &parameters,
+            )?,
+            None => self.buffers.lightbuffer.draw(
+                EmptyVertexAttributes { len: 3 },
+                NoIndices(TrianglesList),
+                program,
+                &UniformsChain(&uniforms, &gbuffer.textures()),
+                &parameters,
+            )?,
+        }
+        Ok(())
+    }
+
+    // Draws the sky over every pixel without geometry, replacing any light there. Drawn after
+    // the lights so they can't add to it
+    pub fn draw_sky(&mut self, sky: &SkyPass, camera: &PCamera) -> Result<(), Box<Error>> {
+        let uniforms = uniform! {
+            inv_projection: *camera.inv_view_matrix().as_ref(),
+        };
+        let gbuffer = self.buffers.gbuffer;
+        self.buffers.lightbuffer.draw(
+            EmptyVertexAttributes { len: 3 },
+            NoIndices(TrianglesList),
+            sky.program(),
+            &UniformsChain(&UniformsChain(&uniforms, sky), &gbuffer.textures()),
+            &Default::default(),
+        )?;
+        Ok(())
+    }
+
+    // Shades all of `lights` in a single full-screen pass, without shadows. `lights` must have
+    // been updated with the same camera. The depth, stencil and blending of `draw_parameters`
+    // are replaced
+    pub fn draw_clustered(
+        &mut self,
+        shininess: f32,
+        lights: &ClusteredLights,
+        camera: &PCamera,
+        draw_parameters: &DrawParameters,
+    ) -> Result<(), Box<Error>> {
+        let grid = match lights.grid() {
+            Some(grid) => grid,
+            None => return Ok(()),
+        };
+        let dimensions = grid.dimensions;
+        let perspective_mat = camera.projection_matrix();
+        let orthographic = match camera.projection {
+            Projection::Orthographic(_) => true,
+            Projection::Perspective(_) => false,
+        };
+        let uniforms = uniform! {
+            eye: *camera.position.coords.as_ref(),
+            inv_projection: *camera.inv_view_matrix().as_ref(),
+            shininess: shininess,
+            T1: perspective_mat[(2, 2)],
+            T2: perspective_mat[(2, 3)],
+            orthographic: orthographic,
+            cluster_dims: [dimensions[0] as i32, dimensions[1] as i32, dimensions[2] as i32],
+            znear: grid.near,
+            zfar: grid.far,
+            light_data: &lights.light_data,
+            cluster_offsets: &lights.cluster_offsets,
+            cluster_indices: &lights.cluster_indices,
+        };
+        let parameters = DrawParameters {
+            blend: additive_blend(),
+            depth: Depth {
+                test: DepthTest::Overwrite,
+                write: false,
+                ..Default::default()
+            },
+            stencil: Stencil::default(),
+            ..draw_parameters.clone()
+        };
+
+        let gbuffer = self.buffers.gbuffer;
+        self.buffers.lightbuffer.draw(
+            EmptyVertexAttributes { len: 3 },
+            NoIndices(TrianglesList),
+            &lights.program,
+            &UniformsChain(&uniforms, &gbuffer.textures()),
+            &parameters,
         )?;
         Ok(())
     }
 
-    pub fn next_stage(&mut self) {
-        self.stage = RenderStage::PrePass;
+    // Ends the frame and returns the lit image, e.g. as the source of a `PostChain`.
+    // `begin_prepass` has to be called to draw again
+    pub fn finish(self) -> &'a Texture2d {
+        let gbuffer = self.buffers.gbuffer;
+        &gbuffer.light
+    }
+}
+
+fn additive_blend() -> Blend {
+    let add = BlendingFunction::Addition {
+        source: LinearBlendingFactor::One,
+        destination: LinearBlendingFactor::One,
+    };
+    Blend {
+        color: add,
+        alpha: add,
+        constant_value: (0.0, 0.0, 0.0, 0.0),
     }
 }
@@ -0,0 +1,303 @@
+// Image based lighting. An equirectangular environment is turned into a cube map, then baked
+// into a diffuse irradiance map, a specular map prefiltered for increasing roughness down its
+// mip chain and a BRDF lookup table for the split sum approximation. `LightingPass::draw_ambient`
+// applies them to the G-buffer
+
+use glium::backend::glutin::Display;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::{
+    ClientFormat, CubeLayer, Cubemap,
+    MipmapsOption::{EmptyMipmaps, EmptyMipmapsMax, NoMipmap},
+    RawImage2d, Texture2d, UncompressedFloatFormat,
+};
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction,
+    UniformValue, Uniforms,
+};
+use glium::Program;
+use image::hdr::HDRDecoder;
+use material::UniformsChain;
+use post::draw_fullscreen;
+use preprocess::Defines;
+use program::fullscreen_program;
+use readback::FloatImage;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use Vec3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EnvironmentSettings {
+    // Width of each face of the environment cube map
+    pub cube_size: u32,
+    pub irradiance_size: u32,
+    // Width of the main level of the prefiltered map
+    pub prefiltered_size: u32,
+    // Mip levels of the prefiltered map, from roughness 0 on the main level to 1 on the last
+    pub prefiltered_levels: u32,
+    // Samples per texel when prefiltering and building the lookup table
+    pub samples: u32,
+    pub lut_size: u32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> EnvironmentSettings {
+        EnvironmentSettings {
+            cube_size: 512,
+            irradiance_size: 32,
+            prefiltered_size: 128,
+            prefiltered_levels: 5,
+            samples: 1024,
+            lut_size: 256,
+        }
+    }
+}
+
+pub struct Environment {
+    // The environment itself, with a mip chain
+    pub cubemap: Cubemap,
+    pub irradiance: Cubemap,
+    pub prefiltered: Cubemap,
+    pub brdf_lut: Texture2d,
+    // Scales the ambient light, e.g. to match the brightness of the other lights
+    pub intensity: f32,
+}
+
+impl Environment {
+    // Loads and bakes an equirectangular Radiance .hdr image
+    pub fn load<P: AsRef<Path>>(
+        display: &Display,
+        path: P,
+        settings: &EnvironmentSettings,
+    ) -> Result<Environment, Box<Error>> {
+        let equirect = equirect_texture(display, &load_hdr(path)?)?;
+        Environment::from_equirect(display, &equirect, settings)
+    }
+
+    pub fn from_equirect(
+        display: &Display,
+        equirect: &Texture2d,
+        settings: &EnvironmentSettings,
+    ) -> Result<Environment, Box<Error>> {
+        let cubemap = Cubemap::empty_with_format(
+            display,
+            UncompressedFloatFormat::F16F16F16,
+            EmptyMipmaps,
+            settings.cube_size,
+        )?;
+        let program = fullscreen_program(display, "ibl/equirect_to_cube.glsl", &Defines::new())?;
+        let uniforms = uniform! {
+            equirect_tex: equirect.sampled().wrap_function(SamplerWrapFunction::Repeat),
+        };
+        draw_cube_faces(display, &cubemap, 0, &program, &uniforms)?;
+        Environment::from_cubemap(display, cubemap, settings)
+    }
+
+    // Bakes an environment cube map. Its mip chain is regenerated from the main level
+    pub fn from_cubemap(
+        display: &Display,
+        cubemap: Cubemap,
+        settings: &EnvironmentSettings,
+    ) -> Result<Environment, Box<Error>> {
+        // Safe since every face of the main level has been drawn or uploaded
+        unsafe {
+            cubemap.generate_mipmaps();
+        }
+        let source_size = cubemap.width() as f32;
+
+        let irradiance = Cubemap::empty_with_format(
+            display,
+            UncompressedFloatFormat::F16F16F16,
+            NoMipmap,
+            settings.irradiance_size,
+        )?;
+        let program = fullscreen_program(display, "ibl/irradiance.glsl", &Defines::new())?;
+        let uniforms = uniform! {
+            environment: cubemap.sampled().minify_filter(MinifySamplerFilter::LinearMipmapLinear),
+            // Roughly a texel per integration step
+            source_lod: (source_size / 64.0).log2().max(0.0),
+        };
+        draw_cube_faces(display, &irradiance, 0, &program, &uniforms)?;
+
+        let levels = settings.prefiltered_levels.max(1);
+        let prefiltered = Cubemap::empty_with_format(
+            display,
+            UncompressedFloatFormat::F16F16F16,
+            EmptyMipmapsMax(levels - 1),
+            settings.prefiltered_size,
+        )?;
+        let program = fullscreen_program(display, "ibl/prefilter.glsl", &Defines::new())?;
+        for level in 0..levels {
+            let roughness = if levels > 1 {
+                level as f32 / (levels - 1) as f32
+            } else {
+                0.0
+            };
+            let uniforms = uniform! {
+                environment: cubemap.sampled().minify_filter(MinifySamplerFilter::LinearMipmapLinear),
+                roughness: roughness,
+                sample_count: settings.samples as i32,
+                source_size: source_size,
+            };
+            draw_cube_faces(display, &prefiltered, level, &program, &uniforms)?;
+        }
+
+        Ok(Environment {
+            cubemap,
+            irradiance,
+            prefiltered,
+            brdf_lut: brdf_lut(display, settings.lut_size, settings.samples)?,
+            intensity: 1.0,
+        })
+    }
+}
+
+// Everything the ambient lighting shader reads from the environment
+impl Uniforms for Environment {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        let clamp = (
+            SamplerWrapFunction::Clamp,
+            SamplerWrapFunction::Clamp,
+            SamplerWrapFunction::Clamp,
+        );
+        let linear = SamplerBehavior {
+            wrap_function: clamp,
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            ..Default::default()
+        };
+        let mipmapped = SamplerBehavior {
+            minify_filter: MinifySamplerFilter::LinearMipmapLinear,
+            ..linear
+        };
+
+        output("irradiance_map", UniformValue::Cubemap(&self.irradiance, Some(linear)));
+        output("prefiltered_map", UniformValue::Cubemap(&self.prefiltered, Some(mipmapped)));
+        output("brdf_lut", UniformValue::Texture2d(&self.brdf_lut, Some(linear)));
+        output(
+            "prefiltered_lod",
+            UniformValue::Float((self.prefiltered.get_mipmap_levels() - 1) as f32),
+        );
+        output("ambient_intensity", UniformValue::Float(self.intensity));
+    }
+}
+
+// Reads a Radiance .hdr image. Rows are stored top to bottom like any `FloatImage`
+pub fn load_hdr<P: AsRef<Path>>(path: P) -> Result<FloatImage, Box<Error>> {
+    let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+
+    let mut image = FloatImage::new(metadata.width, metadata.height, 3);
+    for (pixel, texel) in pixels.iter().zip(image.data.chunks_mut(3)) {
+        texel.copy_from_slice(&pixel.data);
+    }
+    Ok(image)
+}
+
+// Uploads an equirectangular panorama as a float texture, bottom row first as OpenGL expects
+pub fn equirect_texture(display: &Display, image: &FloatImage) -> Result<Texture2d, Box<Error>> {
+    let rgb = image.with_channels(3);
+    let data: Vec<f32> = rgb
+        .data
+        .chunks(rgb.width as usize * 3)
+        .rev()
+        .flat_map(|row| row.iter().cloned())
+        .collect();
+    let raw = RawImage2d {
+        data: Cow::Owned(data),
+        width: rgb.width,
+        height: rgb.height,
+        format: ClientFormat::F32F32F32,
+    };
+    Ok(Texture2d::with_format(
+        display,
+        raw,
+        UncompressedFloatFormat::F32F32F32,
+        NoMipmap,
+    )?)
+}
+
+// The split sum lookup table indexed by N.V and roughness. Only depends on the BRDF, so one can
+// be shared by every environment
+pub fn brdf_lut(display: &Display, size: u32, samples: u32) -> Result<Texture2d, Box<Error>> {
+    let lut = Texture2d::empty_with_format(
+        display,
+        UncompressedFloatFormat::F16F16,
+        NoMipmap,
+        size,
+        size,
+    )?;
+    let program = fullscreen_program(display, "ibl/brdf_lut.glsl", &Defines::new())?;
+    let uniforms = uniform! {
+        sample_count: samples as i32,
+    };
+    draw_fullscreen(&mut SimpleFrameBuffer::new(display, &lut)?, &program, &uniforms)?;
+    Ok(lut)
+}
+
+// The OpenGL cube map faces with the directions the face's centre, +u and +v point in
+pub fn cube_faces() -> [(CubeLayer, Vec3, Vec3, Vec3); 6] {
+    [
+        (
+            CubeLayer::PositiveX,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, -1.0, 0.0),
+        ),
+        (
+            CubeLayer::NegativeX,
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, -1.0, 0.0),
+        ),
+        (
+            CubeLayer::PositiveY,
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ),
+        (
+            CubeLayer::NegativeY,
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ),
+        (
+            CubeLayer::PositiveZ,
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+        ),
+        (
+            CubeLayer::NegativeZ,
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+        ),
+    ]
+}
+
+// Draws a full-screen program over each face of a mip level of `target`. The program gets the
+// face as the `face_forward`, `face_right` and `face_up` uniforms of ibl/common.glsl
+fn draw_cube_faces<U: Uniforms>(
+    display: &Display,
+    target: &Cubemap,
+    level: u32,
+    program: &Program,
+    uniforms: &U,
+) -> Result<(), Box<Error>> {
+    let mipmap = target.mipmap(level).ok_or("mip level out of range")?;
+    for &(layer, forward, right, up) in cube_faces().iter() {
+        let face = uniform! {
+            face_forward: *forward.as_ref(),
+            face_right: *right.as_ref(),
+            face_up: *up.as_ref(),
+        };
+        let mut framebuffer = SimpleFrameBuffer::new(display, mipmap.image(layer))?;
+        draw_fullscreen(&mut framebuffer, program, &UniformsChain(uniforms, &face))?;
+    }
+    Ok(())
+}
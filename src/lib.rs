@@ -1,12 +1,31 @@
 extern crate nalgebra as na;
 #[macro_use]
 extern crate glium;
+extern crate memmap;
+extern crate image;
 
 pub mod math;
 pub mod test;
 pub mod camera;
 pub mod gbuffer;
 pub mod render_object;
+pub mod mesh;
+pub mod texture;
+pub mod assets;
+pub mod watch;
+pub mod program;
+pub mod preprocess;
+pub mod material;
+pub mod readback;
+pub mod debug;
+pub mod post;
+pub mod light;
+pub mod cluster;
+pub mod shadow;
+pub mod ibl;
+pub mod sky;
+pub mod ssao;
+pub mod ssr;
 
 pub type Vec3 = na::Vector3<f32>;
 pub type Vec2 = na::Vector2<f32>;
@@ -15,6 +34,7 @@ pub type Pnt3 = na::Point3<f32>;
 pub type PV = na::Perspective3<f32>;
 pub type OV = na::Orthographic3<f32>;
 
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
     pub position: [f32; 3],
@@ -0,0 +1,702 @@
+use camera::PCamera;
+use cluster::{ClusterGrid, LightBounds};
+use glium::backend::glutin::Display;
+use glium::texture::buffer_texture::{BufferTexture, BufferTextureType};
+use glium::uniforms::{UniformValue, Uniforms};
+use glium::{Program, VertexBuffer};
+use material::MaterialModel;
+use na::{Rotation3, Vector4};
+use program::{clustered_lighting_program, light_stencil_program};
+use std::error::Error;
+use std::f32::consts::PI;
+use {Mat4, SimpleVertex, Vec3};
+
+// RGBA texels per light in `ClusteredLights`, see `Light::pack`
+pub const LIGHT_TEXELS: usize = 6;
+
+// How a light fades out towards its radius. Every curve reaches zero at the radius so the
+// light volume can be cut off there
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Attenuation {
+    // Constant strength inside the radius
+    None,
+    Linear,
+    // Physically based falloff, windowed to reach zero at the radius
+    InverseSquare,
+}
+
+impl Attenuation {
+    // The `attenuation` uniform of the lighting shader
+    fn index(&self) -> i32 {
+        match self {
+            Attenuation::None => 0,
+            Attenuation::Linear => 1,
+            Attenuation::InverseSquare => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AreaShape {
+    Sphere { radius: f32 },
+    // A two sided rectangle spanning centre +- right +- up
+    Rectangle { right: Vec3, up: Vec3 },
+}
+
+impl AreaShape {
+    // Distance from the centre to the furthest point of the shape
+    pub fn extent(&self) -> f32 {
+        match self {
+            AreaShape::Sphere { radius } => *radius,
+            AreaShape::Rectangle { right, up } => (right + up).norm(),
+        }
+    }
+}
+
+// Colours are linear and can go above 1 since lights are accumulated in HDR
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Light {
+    Point {
+        position: Vec3,
+        colour: [f32; 3],
+        radius: f32,
+        attenuation: Attenuation,
+    },
+    // Lights the whole screen from one direction, e.g. the sun
+    Directional {
+        direction: Vec3,
+        colour: [f32; 3],
+    },
+    // Angles are from the centre of the cone in radians. `outer_angle` must be less than PI / 2
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        colour: [f32; 3],
+        radius: f32,
+        attenuation: Attenuation,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    // Shaded with the representative point approximation: specular uses the point of the shape
+    // closest to the reflected view ray, diffuse the point closest to the surface
+    Area {
+        position: Vec3,
+        shape: AreaShape,
+        colour: [f32; 3],
+        radius: f32,
+        attenuation: Attenuation,
+    },
+}
+
+// Which mesh a light is drawn with, see `LightVolumes`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightVolume {
+    Sphere,
+    Cone,
+    FullScreen,
+}
+
+impl Light {
+    pub fn point(position: Vec3, colour: [f32; 3], radius: f32) -> Light {
+        Light::Point {
+            position,
+            colour,
+            radius,
+            attenuation: Attenuation::InverseSquare,
+        }
+    }
+
+    pub fn directional(direction: Vec3, colour: [f32; 3]) -> Light {
+        Light::Directional {
+            direction: direction.normalize(),
+            colour,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        colour: [f32; 3],
+        radius: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Light {
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            colour,
+            radius,
+            attenuation: Attenuation::InverseSquare,
+            inner_angle,
+            outer_angle,
+        }
+    }
+
+    pub fn area(position: Vec3, shape: AreaShape, colour: [f32; 3], radius: f32) -> Light {
+        Light::Area {
+            position,
+            shape,
+            colour,
+            radius,
+            attenuation: Attenuation::InverseSquare,
+        }
+    }
+
+    pub fn colour(&self) -> [f32; 3] {
+        match self {
+            Light::Point { colour, .. }
+            | Light::Directional { colour, .. }
+            | Light::Spot { colour, .. }
+            | Light::Area { colour, .. } => *colour,
+        }
+    }
+
+    pub fn position(&self) -> Option<Vec3> {
+        match self {
+            Light::Point { position, .. }
+            | Light::Spot { position, .. }
+            | Light::Area { position, .. } => Some(*position),
+            Light::Directional { .. } => None,
+        }
+    }
+
+    // Distance past which the light has no effect, None if it has no limit
+    pub fn radius(&self) -> Option<f32> {
+        match self {
+            Light::Point { radius, .. } | Light::Spot { radius, .. } => Some(*radius),
+            Light::Area { radius, shape, .. } => Some(radius + shape.extent()),
+            Light::Directional { .. } => None,
+        }
+    }
+
+    pub fn volume(&self) -> LightVolume {
+        match self {
+            Light::Point { .. } | Light::Area { .. } => LightVolume::Sphere,
+            Light::Spot { .. } => LightVolume::Cone,
+            Light::Directional { .. } => LightVolume::FullScreen,
+        }
+    }
+
+    // Transforms the unit volume from `LightVolumes` to cover everything the light reaches
+    pub fn volume_matrix(&self) -> Mat4 {
+        match self {
+            Light::Point { position, .. } | Light::Area { position, .. } => {
+                let radius = self.radius().expect("point and area lights have a radius");
+                Mat4::new_translation(position) * Mat4::new_scaling(radius)
+            }
+            Light::Spot {
+                position,
+                direction,
+                radius,
+                outer_angle,
+                ..
+            } => {
+                let rotation = Rotation3::rotation_between(&Vec3::z(), direction)
+                    .unwrap_or_else(|| Rotation3::from_axis_angle(&Vec3::x_axis(), PI));
+                let base = radius * outer_angle.tan();
+                Mat4::new_translation(position)
+                    * rotation.to_homogeneous()
+                    * Mat4::new_nonuniform_scaling(&Vec3::new(base, base, *radius))
+            }
+            Light::Directional { .. } => Mat4::identity(),
+        }
+    }
+
+    // The `light_type` uniform of the lighting shader
+    fn index(&self) -> i32 {
+        match self {
+            Light::Point { .. } => 0,
+            Light::Directional { .. } => 1,
+            Light::Spot { .. } => 2,
+            Light::Area { .. } => 3,
+        }
+    }
+}
+
+// The values the lighting shaders see for a light, `LightData` in lighting/shade.glsl
+#[derive(Debug, PartialEq)]
+struct ShaderLight {
+    kind: i32,
+    position: Vec3,
+    direction: Vec3,
+    colour: [f32; 3],
+    radius: f32,
+    attenuation: i32,
+    cos_inner: f32,
+    cos_outer: f32,
+    area_shape: i32,
+    area_radius: f32,
+    area_right: Vec3,
+    area_up: Vec3,
+}
+
+impl Light {
+    // Every field is set for every type so a shader never sees values from another light
+    fn shader_light(&self) -> ShaderLight {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let (position, direction) = match self {
+            Light::Point { position, .. } | Light::Area { position, .. } => (*position, zero),
+            Light::Spot {
+                position,
+                direction,
+                ..
+            } => (*position, *direction),
+            Light::Directional { direction, .. } => (zero, *direction),
+        };
+        let attenuation = match self {
+            Light::Point { attenuation, .. }
+            | Light::Spot { attenuation, .. }
+            | Light::Area { attenuation, .. } => *attenuation,
+            Light::Directional { .. } => Attenuation::None,
+        };
+        let radius = match self {
+            Light::Point { radius, .. }
+            | Light::Spot { radius, .. }
+            | Light::Area { radius, .. } => *radius,
+            Light::Directional { .. } => 0.0,
+        };
+        let (cos_inner, cos_outer) = match self {
+            Light::Spot {
+                inner_angle,
+                outer_angle,
+                ..
+            } => (inner_angle.cos(), outer_angle.cos()),
+            _ => (-1.0, -1.0),
+        };
+        let (area_shape, area_radius, area_right, area_up) = match self {
+            Light::Area {
+                shape: AreaShape::Rectangle { right, up },
+                ..
+            } => (1, 0.0, *right, *up),
+            Light::Area {
+                shape: AreaShape::Sphere { radius },
+                ..
+            } => (0, *radius, zero, zero),
+            _ => (0, 0.0, zero, zero),
+        };
+
+        ShaderLight {
+            kind: self.index(),
+            position,
+            direction,
+            colour: self.colour(),
+            radius,
+            attenuation: attenuation.index(),
+            cos_inner,
+            cos_outer,
+            area_shape,
+            area_radius,
+            area_right,
+            area_up,
+        }
+    }
+
+    // The light as `LIGHT_TEXELS` RGBA texels, read back by `fetch_light` in
+    // lighting/clustered.glsl. Integers are stored as floats
+    pub fn pack(&self) -> [[f32; 4]; LIGHT_TEXELS] {
+        let light = self.shader_light();
+        [
+            [light.position.x, light.position.y, light.position.z, light.radius],
+            [light.colour[0], light.colour[1], light.colour[2], light.kind as f32],
+            [light.direction.x, light.direction.y, light.direction.z, light.attenuation as f32],
+            [light.cos_inner, light.cos_outer, light.area_shape as f32, light.area_radius],
+            [light.area_right.x, light.area_right.y, light.area_right.z, 0.0],
+            [light.area_up.x, light.area_up.y, light.area_up.z, 0.0],
+        ]
+    }
+}
+
+impl Uniforms for Light {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        let light = self.shader_light();
+        output("light_type", UniformValue::SignedInt(light.kind));
+        output("light_pos", UniformValue::Vec3(*light.position.as_ref()));
+        output("light_dir", UniformValue::Vec3(*light.direction.as_ref()));
+        output("light_colour", UniformValue::Vec3(light.colour));
+        output("light_radius", UniformValue::Float(light.radius));
+        output("attenuation", UniformValue::SignedInt(light.attenuation));
+        output("cos_inner", UniformValue::Float(light.cos_inner));
+        output("cos_outer", UniformValue::Float(light.cos_outer));
+        output("area_shape", UniformValue::SignedInt(light.area_shape));
+        output("area_radius", UniformValue::Float(light.area_radius));
+        output("area_right", UniformValue::Vec3(*light.area_right.as_ref()));
+        output("area_up", UniformValue::Vec3(*light.area_up.as_ref()));
+    }
+}
+
+// The meshes and programs lights are drawn with. The sphere has a radius of 1 around the
+// origin and the cone has its tip at the origin and a base of radius 1 at z = 1. Both must fully
+// contain the unit shape, so a low-poly mesh should have its vertices outside it, and be closed
+// with counter-clockwise faces for the stencil pass
+pub struct LightVolumes {
+    pub sphere: VertexBuffer<SimpleVertex>,
+    pub cone: VertexBuffer<SimpleVertex>,
+    // A triangle covering clip space, drawn with identity matrices
+    pub fullscreen: VertexBuffer<SimpleVertex>,
+    // Marks the pixels inside a volume in the stencil buffer
+    pub(crate) stencil_program: Program,
+}
+
+impl LightVolumes {
+    pub fn new(
+        display: &Display,
+        sphere: VertexBuffer<SimpleVertex>,
+        cone: VertexBuffer<SimpleVertex>,
+    ) -> Result<LightVolumes, Box<Error>> {
+        let fullscreen = VertexBuffer::new(
+            display,
+            &[
+                SimpleVertex { position: [-1.0, -1.0, 0.0] },
+                SimpleVertex { position: [3.0, -1.0, 0.0] },
+                SimpleVertex { position: [-1.0, 3.0, 0.0] },
+            ],
+        )?;
+        Ok(LightVolumes {
+            sphere,
+            cone,
+            fullscreen,
+            stencil_program: light_stencil_program(display)?,
+        })
+    }
+
+    // An 80 triangle icosphere and a 16 sided cone
+    pub fn generate(display: &Display) -> Result<LightVolumes, Box<Error>> {
+        let sphere = VertexBuffer::new(display, &icosphere(1))?;
+        let cone = VertexBuffer::new(display, &cone(16))?;
+        LightVolumes::new(display, sphere, cone)
+    }
+
+    pub fn get(&self, volume: LightVolume) -> &VertexBuffer<SimpleVertex> {
+        match volume {
+            LightVolume::Sphere => &self.sphere,
+            LightVolume::Cone => &self.cone,
+            LightVolume::FullScreen => &self.fullscreen,
+        }
+    }
+}
+
+// Lights shaded together in one full-screen pass, see `LightingPass::draw_clustered`. Each
+// frame the lights are assigned to the clusters of a `ClusterGrid` on the CPU and uploaded as
+// buffer textures, so each pixel only loops over the lights of its own cluster. There is no
+// shadow map per light in this pass, so lights that need shadows are drawn one at a time with
+// `LightingPass::draw_shadowed_light` instead
+pub struct ClusteredLights {
+    pub(crate) program: Program,
+    dimensions: [usize; 3],
+    // Rebuilt when the camera's projection changes
+    grid: Option<(Mat4, ClusterGrid)>,
+    pub(crate) light_data: BufferTexture<(f32, f32, f32, f32)>,
+    pub(crate) cluster_offsets: BufferTexture<(u32, u32)>,
+    pub(crate) cluster_indices: BufferTexture<u32>,
+    len: usize,
+}
+
+impl ClusteredLights {
+    // 16 by 9 tiles and 24 depth slices
+    pub fn new(display: &Display, model: MaterialModel) -> Result<ClusteredLights, Box<Error>> {
+        ClusteredLights::with_dimensions(display, [16, 9, 24], model)
+    }
+
+    pub fn with_dimensions(
+        display: &Display,
+        dimensions: [usize; 3],
+        model: MaterialModel,
+    ) -> Result<ClusteredLights, Box<Error>> {
+        // Buffer textures can't be empty, so they start with a single unused element
+        Ok(ClusteredLights {
+            program: clustered_lighting_program(display, model)?,
+            dimensions,
+            grid: None,
+            light_data: BufferTexture::new(display, &[(0.0, 0.0, 0.0, 0.0)], BufferTextureType::Float)?,
+            cluster_offsets: BufferTexture::new(display, &[(0, 0)], BufferTextureType::Unsigned)?,
+            cluster_indices: BufferTexture::new(display, &[0], BufferTextureType::Unsigned)?,
+            len: 0,
+        })
+    }
+
+    pub fn dimensions(&self) -> [usize; 3] {
+        self.dimensions
+    }
+
+    // The grid of the last `update`
+    pub fn grid(&self) -> Option<&ClusterGrid> {
+        self.grid.as_ref().map(|(_, grid)| grid)
+    }
+
+    // Number of lights of the last `update`
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Assigns `lights` to the clusters of `camera`'s frustum and uploads them. Has to be called
+    // again whenever the lights or the camera change
+    pub fn update(
+        &mut self,
+        display: &Display,
+        lights: &[Light],
+        camera: &PCamera,
+    ) -> Result<(), Box<Error>> {
+        let projection = camera.projection_matrix();
+        let rebuild = match self.grid {
+            Some((built_for, _)) => built_for != projection,
+            None => true,
+        };
+        if rebuild {
+            self.grid = Some((projection, ClusterGrid::new(self.dimensions, camera)));
+        }
+        let grid = &self.grid.as_ref().expect("the grid was just built").1;
+
+        let view = camera.look_at_matrix();
+        let bounds: Vec<LightBounds> = lights
+            .iter()
+            .map(|light| LightBounds {
+                centre: light
+                    .position()
+                    .map(|p| {
+                        let centre = view * Vector4::new(p.x, p.y, p.z, 1.0);
+                        Vec3::new(centre.x, centre.y, centre.z)
+                    })
+                    .unwrap_or_else(|| Vec3::new(0.0, 0.0, 0.0)),
+                radius: light.radius(),
+            })
+            .collect();
+        let assignment = grid.assign(&bounds);
+
+        let mut light_data: Vec<(f32, f32, f32, f32)> = lights
+            .iter()
+            .flat_map(|light| light.pack().to_vec())
+            .map(|texel| (texel[0], texel[1], texel[2], texel[3]))
+            .collect();
+        if light_data.is_empty() {
+            light_data.push((0.0, 0.0, 0.0, 0.0));
+        }
+        let mut indices = assignment.indices;
+        if indices.is_empty() {
+            indices.push(0);
+        }
+
+        self.light_data = BufferTexture::dynamic(display, &light_data, BufferTextureType::Float)?;
+        self.cluster_offsets =
+            BufferTexture::dynamic(display, &assignment.offsets, BufferTextureType::Unsigned)?;
+        self.cluster_indices =
+            BufferTexture::dynamic(display, &indices, BufferTextureType::Unsigned)?;
+        self.len = lights.len();
+        Ok(())
+    }
+}
+
+// A subdivided icosahedron whose faces all lie outside the unit sphere
+pub fn icosphere(subdivisions: u32) -> Vec<SimpleVertex> {
+    let t = (1.0 + 5.0f32.sqrt()) * 0.5;
+    let corners = [
+        Vec3::new(-1.0, t, 0.0),
+        Vec3::new(1.0, t, 0.0),
+        Vec3::new(-1.0, -t, 0.0),
+        Vec3::new(1.0, -t, 0.0),
+        Vec3::new(0.0, -1.0, t),
+        Vec3::new(0.0, 1.0, t),
+        Vec3::new(0.0, -1.0, -t),
+        Vec3::new(0.0, 1.0, -t),
+        Vec3::new(t, 0.0, -1.0),
+        Vec3::new(t, 0.0, 1.0),
+        Vec3::new(-t, 0.0, -1.0),
+        Vec3::new(-t, 0.0, 1.0),
+    ];
+    let faces: [[usize; 3]; 20] = [
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    let mut triangles: Vec<[Vec3; 3]> = faces
+        .iter()
+        .map(|f| [corners[f[0]].normalize(), corners[f[1]].normalize(), corners[f[2]].normalize()])
+        .collect();
+
+    for _ in 0..subdivisions {
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = (a + b).normalize();
+                let bc = (b + c).normalize();
+                let ca = (c + a).normalize();
+                vec![[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // The vertices are on the unit sphere so the faces cut into it. Scaling by the distance to
+    // the closest face pushes every face out to touch it
+    let inradius = triangles
+        .iter()
+        .map(|&[a, b, c]| (b - a).cross(&(c - a)).normalize().dot(&a))
+        .fold(1.0f32, |min, distance| min.min(distance));
+
+    triangles
+        .iter()
+        .flat_map(|triangle| triangle.iter().map(|v| simple_vertex(v / inradius)).collect::<Vec<_>>())
+        .collect()
+}
+
+// A cone with its tip at the origin and its base at z = 1, wide enough to contain a base of
+// radius 1
+pub fn cone(segments: u32) -> Vec<SimpleVertex> {
+    // Pushes the edges of the polygon out to the circle rather than the corners
+    let radius = 1.0 / (PI / segments as f32).cos();
+    let ring: Vec<Vec3> = (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * 2.0 * PI;
+            Vec3::new(angle.cos() * radius, angle.sin() * radius, 1.0)
+        })
+        .collect();
+
+    let tip = Vec3::new(0.0, 0.0, 0.0);
+    let centre = Vec3::new(0.0, 0.0, 1.0);
+    let mut vertices = Vec::with_capacity(segments as usize * 6);
+    for i in 0..ring.len() {
+        let current = ring[i];
+        let next = ring[(i + 1) % ring.len()];
+        vertices.extend_from_slice(&[
+            simple_vertex(tip),
+            simple_vertex(next),
+            simple_vertex(current),
+            simple_vertex(centre),
+            simple_vertex(current),
+            simple_vertex(next),
+        ]);
+    }
+    vertices
+}
+
+fn simple_vertex(position: Vec3) -> SimpleVertex {
+    SimpleVertex {
+        position: *position.as_ref(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(vertices: &[SimpleVertex]) -> Vec<Vec3> {
+        vertices
+            .iter()
+            .map(|v| Vec3::new(v.position[0], v.position[1], v.position[2]))
+            .collect()
+    }
+
+    // For each triangle, its outward normal if wound counter-clockwise and a point on it
+    fn planes(vertices: &[SimpleVertex]) -> Vec<(Vec3, Vec3)> {
+        positions(vertices)
+            .chunks(3)
+            .map(|t| ((t[1] - t[0]).cross(&(t[2] - t[0])).normalize(), t[0]))
+            .collect()
+    }
+
+    // Checks every face of a convex volume faces away from `inside` and that no point of `shape`
+    // lies in front of any face
+    fn assert_contains(vertices: &[SimpleVertex], inside: Vec3, shape: &[Vec3]) {
+        assert_eq!(vertices.len() % 3, 0);
+        for (normal, point) in planes(vertices) {
+            assert!(normal.dot(&(point - inside)) > 0.0, "face wound inwards");
+            for p in shape {
+                assert!(normal.dot(&(p - point)) <= 1e-5, "{:?} is outside the volume", p);
+            }
+        }
+    }
+
+    #[test]
+    fn icosphere_contains_the_unit_sphere() {
+        let sphere = icosphere(1);
+        assert_eq!(sphere.len(), 80 * 3);
+        for position in positions(&sphere) {
+            assert!(position.norm() >= 1.0);
+        }
+
+        let mut shape = Vec::new();
+        for i in 0..24 {
+            for j in 0..=12 {
+                let (azimuth, polar) = (i as f32 / 24.0 * 2.0 * PI, j as f32 / 12.0 * PI);
+                shape.push(Vec3::new(
+                    polar.sin() * azimuth.cos(),
+                    polar.sin() * azimuth.sin(),
+                    polar.cos(),
+                ));
+            }
+        }
+        assert_contains(&sphere, Vec3::new(0.0, 0.0, 0.0), &shape);
+    }
+
+    #[test]
+    fn cone_contains_the_unit_cone() {
+        let cone = cone(16);
+        assert_eq!(cone.len(), 16 * 6);
+        // Besides the tip and the centre of the base every vertex is on the rim, outside the
+        // unit circle
+        for position in positions(&cone) {
+            let xy = (position.x * position.x + position.y * position.y).sqrt();
+            assert!(xy == 0.0 || (position.z == 1.0 && xy >= 1.0), "{:?}", position);
+        }
+
+        // The tip and the rim of the base, everything else lies between them
+        let mut shape = vec![Vec3::new(0.0, 0.0, 0.0)];
+        for i in 0..360 {
+            let angle = i as f32 / 360.0 * 2.0 * PI;
+            shape.push(Vec3::new(angle.cos(), angle.sin(), 1.0));
+        }
+        assert_contains(&cone, Vec3::new(0.0, 0.0, 0.5), &shape);
+    }
+
+    // Mirrors `fetch_light` in lighting/clustered.glsl
+    fn fetch(texels: &[[f32; 4]; LIGHT_TEXELS]) -> ShaderLight {
+        let vec3 = |t: [f32; 4]| Vec3::new(t[0], t[1], t[2]);
+        let int = |value: f32| (value + 0.5).floor() as i32;
+        let (position_radius, colour_kind) = (texels[0], texels[1]);
+        let (direction_attenuation, cone_area) = (texels[2], texels[3]);
+        ShaderLight {
+            kind: int(colour_kind[3]),
+            position: vec3(position_radius),
+            direction: vec3(direction_attenuation),
+            colour: [colour_kind[0], colour_kind[1], colour_kind[2]],
+            radius: position_radius[3],
+            attenuation: int(direction_attenuation[3]),
+            cos_inner: cone_area[0],
+            cos_outer: cone_area[1],
+            area_shape: int(cone_area[2]),
+            area_radius: cone_area[3],
+            area_right: vec3(texels[4]),
+            area_up: vec3(texels[5]),
+        }
+    }
+
+    #[test]
+    fn packed_lights_match_fetch_light() {
+        let shader = include_str!("shaders/lighting/clustered.glsl");
+        assert!(shader.contains(&format!("int base = index * {};", LIGHT_TEXELS)));
+
+        // Every field has a different value so a swapped texel or channel shows up
+        let lights = [
+            Light::point(Vec3::new(1.0, 2.0, 3.0), [4.0, 5.0, 6.0], 7.0),
+            Light::directional(Vec3::new(0.0, -1.0, 0.0), [0.5, 0.25, 0.125]),
+            Light::spot(Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 1.0), [4.0, 5.0, 6.0], 7.0, 0.25, 0.5),
+            Light::area(
+                Vec3::new(1.0, 2.0, 3.0),
+                AreaShape::Rectangle {
+                    right: Vec3::new(8.0, 9.0, 10.0),
+                    up: Vec3::new(11.0, 12.0, 13.0),
+                },
+                [4.0, 5.0, 6.0],
+                7.0,
+            ),
+            Light::area(Vec3::new(1.0, 2.0, 3.0), AreaShape::Sphere { radius: 0.75 }, [4.0, 5.0, 6.0], 7.0),
+        ];
+        for light in &lights {
+            assert_eq!(fetch(&light.pack()), light.shader_light());
+        }
+    }
+}
@@ -0,0 +1,580 @@
+use assets::TextureHandle;
+use glium::buffer::Content;
+use glium::program;
+use glium::uniforms::{
+    LayoutMismatchError, SamplerBehavior, UniformBlock, UniformBuffer, UniformType, UniformValue,
+    Uniforms,
+};
+use glium::Program;
+use preprocess::{flags, Defines};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+// How the G-buffer describes surfaces and how the lighting pass shades them. The prepass,
+// G-buffer layout and lighting programs all have to agree on it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MaterialModel {
+    // An RGB "specular" attachment and a `shininess` per light draw
+    BlinnPhong,
+    // Cook-Torrance with a "material" attachment holding metallic, roughness and ambient
+    // occlusion, see `GBufferLayout::metallic_roughness`
+    MetallicRoughness,
+}
+
+impl MaterialModel {
+    // Defines selecting the model in the prepass and lighting shaders
+    pub fn defines(&self) -> Defines {
+        match self {
+            MaterialModel::BlinnPhong => Defines::new(),
+            MaterialModel::MetallicRoughness => flags(&["METALLIC_ROUGHNESS"]),
+        }
+    }
+}
+
+impl Default for MaterialModel {
+    fn default() -> MaterialModel {
+        MaterialModel::BlinnPhong
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+    pub ty: UniformType,
+    // The number of elements if the uniform is an array
+    pub size: Option<usize>,
+}
+
+impl UniformInfo {
+    pub fn is_sampler(&self) -> bool {
+        is_sampler(self.ty)
+    }
+}
+
+// The active uniforms, samplers and uniform blocks of a compiled program
+#[derive(Clone, Debug)]
+pub struct ProgramReflection {
+    pub uniforms: HashMap<String, UniformInfo>,
+    // Block name to its size in bytes
+    pub blocks: HashMap<String, usize>,
+}
+
+impl ProgramReflection {
+    pub fn new(program: &Program) -> ProgramReflection {
+        let uniforms = program
+            .uniforms()
+            .map(|(name, uniform)| {
+                let info = UniformInfo {
+                    ty: uniform.ty,
+                    size: uniform.size,
+                };
+                (uniform_name(name).to_string(), info)
+            })
+            .collect();
+        let blocks = program
+            .get_uniform_blocks()
+            .iter()
+            .map(|(name, block)| (name.clone(), block.size))
+            .collect();
+
+        ProgramReflection { uniforms, blocks }
+    }
+
+    pub fn samplers(&self) -> impl Iterator<Item = (&String, &UniformInfo)> {
+        self.uniforms.iter().filter(|(_, info)| info.is_sampler())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (&String, &UniformInfo)> {
+        self.uniforms.iter().filter(|(_, info)| !info.is_sampler())
+    }
+
+    // Checks every uniform and block of the program is provided, either by `params` or by one of
+    // the `provided` names (e.g. the uniforms a draw call sets itself), and that the params have
+    // the right types. Params the program doesn't use are ignored so one material can be shared
+    // between shader permutations
+    pub fn validate(&self, params: &MaterialParams, provided: &[&str]) -> Result<(), MaterialError> {
+        for name in self.uniforms.keys().chain(self.blocks.keys()) {
+            match params.get(name) {
+                Some(value) => self.check(name, value)?,
+                None if provided.contains(&name.as_str()) => {}
+                None => return Err(MaterialError::Missing(name.clone())),
+            }
+        }
+        Ok(())
+    }
+
+    // Checks `value` has the type the program expects for `name`, if it uses it. Materials only
+    // hold single values, so arrays have to be set by the draw call
+    fn check(&self, name: &str, value: &MaterialValue) -> Result<(), MaterialError> {
+        if let Some(uniform) = self.uniforms.get(name) {
+            if let Some(size) = uniform.size.filter(|&size| size > 1) {
+                return Err(MaterialError::Array {
+                    name: name.to_string(),
+                    size,
+                    found: value.kind(),
+                });
+            }
+            if !value.matches(uniform.ty) {
+                return Err(MaterialError::Mismatch {
+                    name: name.to_string(),
+                    expected: uniform.ty,
+                    found: value.kind(),
+                });
+            }
+        }
+        match value {
+            MaterialValue::Block(_) => Ok(()),
+            _ if self.blocks.contains_key(name) => Err(MaterialError::NotABlock {
+                name: name.to_string(),
+                found: value.kind(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MaterialError {
+    // A uniform or block used by the program that nothing provides
+    Missing(String),
+    Mismatch {
+        name: String,
+        expected: UniformType,
+        found: &'static str,
+    },
+    NotABlock {
+        name: String,
+        found: &'static str,
+    },
+    Array {
+        name: String,
+        size: usize,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MaterialError::Missing(name) => write!(f, "no value for uniform '{}'", name),
+            MaterialError::Mismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "uniform '{}' is {:?} but the material has a {}",
+                name, expected, found
+            ),
+            MaterialError::NotABlock { name, found } => write!(
+                f,
+                "'{}' is a uniform block but the material has a {}",
+                name, found
+            ),
+            MaterialError::Array { name, size, found } => write!(
+                f,
+                "uniform '{}' is an array of {} but the material has a single {}",
+                name, size, found
+            ),
+        }
+    }
+}
+
+impl Error for MaterialError {
+    fn description(&self) -> &str {
+        match self {
+            MaterialError::Missing(_) => "missing uniform",
+            MaterialError::Mismatch { .. }
+            | MaterialError::NotABlock { .. }
+            | MaterialError::Array { .. } => "mismatched uniform",
+        }
+    }
+}
+
+// Gives a uniform buffer to a material without the material knowing its block type
+pub trait BlockSource {
+    fn block_value(&self) -> UniformValue;
+}
+
+impl<T: ?Sized + UniformBlock + Content> BlockSource for UniformBuffer<T> {
+    fn block_value(&self) -> UniformValue {
+        fn matches<T: ?Sized + UniformBlock + Content>(
+            block: &program::UniformBlock,
+        ) -> Result<(), LayoutMismatchError> {
+            T::matches(&block.layout, 0)
+        }
+
+        UniformValue::Block(self.as_slice_any(), matches::<T>)
+    }
+}
+
+#[derive(Clone)]
+pub enum MaterialValue {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Int(i32),
+    UnsignedInt(u32),
+    Bool(bool),
+    Mat3([[f32; 3]; 3]),
+    Mat4([[f32; 4]; 4]),
+    Texture(TextureHandle, Option<SamplerBehavior>),
+    Block(Rc<BlockSource>),
+}
+
+impl MaterialValue {
+    fn kind(&self) -> &'static str {
+        match self {
+            MaterialValue::Float(_) => "float",
+            MaterialValue::Vec2(_) => "vec2",
+            MaterialValue::Vec3(_) => "vec3",
+            MaterialValue::Vec4(_) => "vec4",
+            MaterialValue::Int(_) => "int",
+            MaterialValue::UnsignedInt(_) => "uint",
+            MaterialValue::Bool(_) => "bool",
+            MaterialValue::Mat3(_) => "mat3",
+            MaterialValue::Mat4(_) => "mat4",
+            MaterialValue::Texture(_, _) => "texture",
+            MaterialValue::Block(_) => "uniform block",
+        }
+    }
+
+    fn matches(&self, ty: UniformType) -> bool {
+        match (self, ty) {
+            (MaterialValue::Float(_), UniformType::Float)
+            | (MaterialValue::Vec2(_), UniformType::FloatVec2)
+            | (MaterialValue::Vec3(_), UniformType::FloatVec3)
+            | (MaterialValue::Vec4(_), UniformType::FloatVec4)
+            | (MaterialValue::Int(_), UniformType::Int)
+            | (MaterialValue::UnsignedInt(_), UniformType::UnsignedInt)
+            | (MaterialValue::Bool(_), UniformType::Bool)
+            | (MaterialValue::Mat3(_), UniformType::FloatMat3)
+            | (MaterialValue::Mat4(_), UniformType::FloatMat4)
+            | (MaterialValue::Texture(_, _), UniformType::Sampler2d) => true,
+            _ => false,
+        }
+    }
+
+    fn as_uniform_value(&self) -> UniformValue {
+        match self {
+            MaterialValue::Float(v) => UniformValue::Float(*v),
+            MaterialValue::Vec2(v) => UniformValue::Vec2(*v),
+            MaterialValue::Vec3(v) => UniformValue::Vec3(*v),
+            MaterialValue::Vec4(v) => UniformValue::Vec4(*v),
+            MaterialValue::Int(v) => UniformValue::SignedInt(*v),
+            MaterialValue::UnsignedInt(v) => UniformValue::UnsignedInt(*v),
+            MaterialValue::Bool(v) => UniformValue::Bool(*v),
+            MaterialValue::Mat3(v) => UniformValue::Mat3(*v),
+            MaterialValue::Mat4(v) => UniformValue::Mat4(*v),
+            MaterialValue::Texture(texture, sampler) => UniformValue::Texture2d(texture, *sampler),
+            MaterialValue::Block(block) => block.block_value(),
+        }
+    }
+}
+
+impl From<f32> for MaterialValue {
+    fn from(value: f32) -> MaterialValue {
+        MaterialValue::Float(value)
+    }
+}
+
+impl From<[f32; 2]> for MaterialValue {
+    fn from(value: [f32; 2]) -> MaterialValue {
+        MaterialValue::Vec2(value)
+    }
+}
+
+impl From<[f32; 3]> for MaterialValue {
+    fn from(value: [f32; 3]) -> MaterialValue {
+        MaterialValue::Vec3(value)
+    }
+}
+
+impl From<[f32; 4]> for MaterialValue {
+    fn from(value: [f32; 4]) -> MaterialValue {
+        MaterialValue::Vec4(value)
+    }
+}
+
+impl From<i32> for MaterialValue {
+    fn from(value: i32) -> MaterialValue {
+        MaterialValue::Int(value)
+    }
+}
+
+impl From<u32> for MaterialValue {
+    fn from(value: u32) -> MaterialValue {
+        MaterialValue::UnsignedInt(value)
+    }
+}
+
+impl From<bool> for MaterialValue {
+    fn from(value: bool) -> MaterialValue {
+        MaterialValue::Bool(value)
+    }
+}
+
+impl From<[[f32; 3]; 3]> for MaterialValue {
+    fn from(value: [[f32; 3]; 3]) -> MaterialValue {
+        MaterialValue::Mat3(value)
+    }
+}
+
+impl From<[[f32; 4]; 4]> for MaterialValue {
+    fn from(value: [[f32; 4]; 4]) -> MaterialValue {
+        MaterialValue::Mat4(value)
+    }
+}
+
+impl From<TextureHandle> for MaterialValue {
+    fn from(value: TextureHandle) -> MaterialValue {
+        MaterialValue::Texture(value, None)
+    }
+}
+
+// Named shader parameters that can be bound to any program. Blocks are matched against the
+// program's uniform blocks, everything else against its uniforms
+#[derive(Clone, Default)]
+pub struct MaterialParams {
+    values: HashMap<String, MaterialValue>,
+}
+
+impl MaterialParams {
+    pub fn new() -> MaterialParams {
+        MaterialParams {
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn set<V: Into<MaterialValue>>(&mut self, name: &str, value: V) -> &mut MaterialParams {
+        self.values.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn set_block<B: BlockSource + 'static>(&mut self, name: &str, block: Rc<B>) -> &mut MaterialParams {
+        self.values.insert(name.to_string(), MaterialValue::Block(block));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MaterialValue> {
+        self.values.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<MaterialValue> {
+        self.values.remove(name)
+    }
+}
+
+impl Uniforms for MaterialParams {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut output: F) {
+        for (name, value) in &self.values {
+            output(name, value.as_uniform_value());
+        }
+    }
+}
+
+// Params checked against the program they are drawn with. The program is reflected and checked
+// once when the material is created instead of on every draw, and `set` only checks the value
+// it changes
+pub struct Material<'a> {
+    program: &'a Program,
+    reflection: ProgramReflection,
+    params: MaterialParams,
+}
+
+impl<'a> Material<'a> {
+    // `provided` are the uniforms the draw calls set themselves, e.g. `OBJECT_UNIFORMS`
+    pub fn new(
+        program: &'a Program,
+        params: MaterialParams,
+        provided: &[&str],
+    ) -> Result<Material<'a>, MaterialError> {
+        let reflection = ProgramReflection::new(program);
+        reflection.validate(&params, provided)?;
+        Ok(Material {
+            program,
+            reflection,
+            params,
+        })
+    }
+
+    pub fn program(&self) -> &'a Program {
+        self.program
+    }
+
+    pub fn params(&self) -> &MaterialParams {
+        &self.params
+    }
+
+    pub fn set<V: Into<MaterialValue>>(&mut self, name: &str, value: V) -> Result<(), MaterialError> {
+        let value = value.into();
+        self.reflection.check(name, &value)?;
+        self.params.values.insert(name.to_string(), value);
+        Ok(())
+    }
+}
+
+impl<'a> Uniforms for Material<'a> {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, output: F) {
+        self.params.visit_values(output);
+    }
+}
+
+// Visits the uniforms of `A` then `B`, e.g. the built-in uniforms of a draw call followed by
+// a material
+pub struct UniformsChain<'a, A: 'a, B: 'a>(pub &'a A, pub &'a B);
+
+impl<'a, A: Uniforms, B: Uniforms> Uniforms for UniformsChain<'a, A, B> {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        self.0.visit_values(&mut output);
+        self.1.visit_values(&mut output);
+    }
+}
+
+// GL reports arrays by their first element, "lights[0]", while materials set them by name
+fn uniform_name(name: &str) -> &str {
+    if name.ends_with("[0]") {
+        &name[..name.len() - 3]
+    } else {
+        name
+    }
+}
+
+fn is_sampler(ty: UniformType) -> bool {
+    // Anything that isn't a plain value is a sampler or an image
+    match ty {
+        UniformType::Float
+        | UniformType::FloatVec2
+        | UniformType::FloatVec3
+        | UniformType::FloatVec4
+        | UniformType::Double
+        | UniformType::DoubleVec2
+        | UniformType::DoubleVec3
+        | UniformType::DoubleVec4
+        | UniformType::Int
+        | UniformType::IntVec2
+        | UniformType::IntVec3
+        | UniformType::IntVec4
+        | UniformType::UnsignedInt
+        | UniformType::UnsignedIntVec2
+        | UniformType::UnsignedIntVec3
+        | UniformType::UnsignedIntVec4
+        | UniformType::Int64
+        | UniformType::Int64Vec2
+        | UniformType::Int64Vec3
+        | UniformType::Int64Vec4
+        | UniformType::UnsignedInt64
+        | UniformType::UnsignedInt64Vec2
+        | UniformType::UnsignedInt64Vec3
+        | UniformType::UnsignedInt64Vec4
+        | UniformType::Bool
+        | UniformType::BoolVec2
+        | UniformType::BoolVec3
+        | UniformType::BoolVec4
+        | UniformType::FloatMat2
+        | UniformType::FloatMat3
+        | UniformType::FloatMat4
+        | UniformType::FloatMat2x3
+        | UniformType::FloatMat2x4
+        | UniformType::FloatMat3x2
+        | UniformType::FloatMat3x4
+        | UniformType::FloatMat4x2
+        | UniformType::FloatMat4x3
+        | UniformType::DoubleMat2
+        | UniformType::DoubleMat3
+        | UniformType::DoubleMat4
+        | UniformType::DoubleMat2x3
+        | UniformType::DoubleMat2x4
+        | UniformType::DoubleMat3x2
+        | UniformType::DoubleMat3x4
+        | UniformType::DoubleMat4x2
+        | UniformType::DoubleMat4x3 => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As reported by GL, with the array by its first element
+    fn reflection() -> ProgramReflection {
+        let uniforms = vec![
+            ("tint", UniformType::FloatVec3, None),
+            ("weights[0]", UniformType::Float, Some(4)),
+            ("model", UniformType::FloatMat4, None),
+            ("normal_matrix", UniformType::FloatMat3, None),
+        ];
+        let uniforms = uniforms
+            .into_iter()
+            .map(|(name, ty, size)| (uniform_name(name).to_string(), UniformInfo { ty, size }))
+            .collect();
+
+        let mut blocks = HashMap::new();
+        blocks.insert("Lights".to_string(), 64);
+        ProgramReflection { uniforms, blocks }
+    }
+
+    fn params() -> MaterialParams {
+        let mut params = MaterialParams::new();
+        params
+            .set("tint", [1.0, 0.5, 0.25])
+            .set("normal_matrix", [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+            .set("unused", 1);
+        params
+    }
+
+    #[test]
+    fn provided_values_are_valid() {
+        reflection().validate(&params(), &["model", "weights", "Lights"]).unwrap();
+    }
+
+    #[test]
+    fn arrays_are_matched_by_name() {
+        // The array is named without "[0]", and a single value can't fill it
+        let mut params = params();
+        params.set("weights", 0.5);
+        match reflection().validate(&params, &["model", "Lights"]) {
+            Err(MaterialError::Array { name, size, found }) => {
+                assert_eq!(name, "weights");
+                assert_eq!(size, 4);
+                assert_eq!(found, "float");
+            }
+            other => panic!("expected an array mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_values_are_reported() {
+        match reflection().validate(&params(), &["model", "Lights"]) {
+            Err(MaterialError::Missing(name)) => assert_eq!(name, "weights"),
+            other => panic!("expected a missing uniform, got {:?}", other),
+        }
+        match reflection().validate(&params(), &["model", "weights"]) {
+            Err(MaterialError::Missing(name)) => assert_eq!(name, "Lights"),
+            other => panic!("expected a missing block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_types_are_reported() {
+        let mut params = params();
+        params.set("tint", 1.0);
+        match reflection().validate(&params, &["model", "weights", "Lights"]) {
+            Err(MaterialError::Mismatch { name, found, .. }) => {
+                assert_eq!(name, "tint");
+                assert_eq!(found, "float");
+            }
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+
+        let mut params = self::params();
+        params.set("Lights", 1.0);
+        match reflection().validate(&params, &["model", "weights"]) {
+            Err(MaterialError::NotABlock { name, .. }) => assert_eq!(name, "Lights"),
+            other => panic!("expected a block mismatch, got {:?}", other),
+        }
+    }
+}
@@ -122,4 +122,138 @@ pub fn clamp(value: f32, min: f32, max: f32) -> f32 {
     else {
         value
     }
-}
\ No newline at end of file
+}
+
+// Octahedral normal encoding used by the G-buffer, matching `encode_normal` in common.glsl.
+// Maps a unit vector to two coordinates in 0..1
+pub fn oct_encode(normal: &Vec3) -> Vec2 {
+    let l1 = normal.x.abs() + normal.y.abs() + normal.z.abs();
+    let mut p = Vec2::new(normal.x / l1, normal.y / l1);
+    if normal.z < 0.0 {
+        p = Vec2::new(
+            (1.0 - p.y.abs()) * sign_not_zero(p.x),
+            (1.0 - p.x.abs()) * sign_not_zero(p.y),
+        );
+    }
+    p * 0.5 + Vec2::new(0.5, 0.5)
+}
+
+// Inverse of `oct_encode`, matching `decode_normal` in common.glsl
+pub fn oct_decode(encoded: &Vec2) -> Vec3 {
+    let p = encoded * 2.0 - Vec2::new(1.0, 1.0);
+    let z = 1.0 - p.x.abs() - p.y.abs();
+    let normal = if z < 0.0 {
+        Vec3::new(
+            (1.0 - p.y.abs()) * sign_not_zero(p.x),
+            (1.0 - p.x.abs()) * sign_not_zero(p.y),
+            z,
+        )
+    } else {
+        Vec3::new(p.x, p.y, z)
+    };
+    normalize(&normal)
+}
+
+fn sign_not_zero(value: f32) -> f32 {
+    if value >= 0.0 {
+        1.0
+    }
+    else {
+        -1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    // The largest angle, in radians, between a direction and its decoded U16U16 G-buffer normal.
+    // A texel is 2 / 65535 wide in the octahedron's [-1, 1] square, so rounding moves a point by
+    // up to half its diagonal, 2.2e-5. Projecting onto the sphere stretches that by up to about
+    // 3 around the face centres, which are only 1 / sqrt(3) from the origin, giving 6.5e-5.
+    // 1e-4 radians is under 0.006 degrees
+    const U16_MAX_ERROR: f32 = 1e-4;
+
+    // The poles, the axes, directions on the z < 0 fold and a Fibonacci sphere in between
+    fn directions() -> Vec<Vec3> {
+        let mut directions = vec![
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, -1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(0.0, 1.0, -0.001),
+            Vec3::new(1.0, 0.0, -0.001),
+            Vec3::new(0.001, 0.0, -1.0),
+            Vec3::new(-0.001, 0.0, -1.0),
+        ];
+        let count = 4096;
+        let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+        for i in 0..count {
+            let z = 1.0 - (i as f32 + 0.5) / count as f32 * 2.0;
+            let radius = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f32;
+            directions.push(Vec3::new(radius * phi.cos(), radius * phi.sin(), z));
+        }
+        directions.iter().map(normalize).collect()
+    }
+
+    fn angle(a: &Vec3, b: &Vec3) -> f32 {
+        // More precise than acos for small angles
+        2.0 * ((a - b).norm() / 2.0).min(1.0).asin()
+    }
+
+    fn quantize(value: f32) -> f32 {
+        (value * 65535.0).round() / 65535.0
+    }
+
+    #[test]
+    fn encoded_normals_are_in_the_unit_square() {
+        for direction in directions() {
+            let encoded = oct_encode(&direction);
+            assert!(encoded.x >= 0.0 && encoded.x <= 1.0, "{:?} -> {:?}", direction, encoded);
+            assert!(encoded.y >= 0.0 && encoded.y <= 1.0, "{:?} -> {:?}", direction, encoded);
+        }
+    }
+
+    #[test]
+    fn lower_hemisphere_is_folded_outside_the_diamond() {
+        for direction in directions() {
+            let p = oct_encode(&direction) * 2.0 - Vec2::new(1.0, 1.0);
+            let l1 = p.x.abs() + p.y.abs();
+            if direction.z < 0.0 {
+                assert!(l1 >= 1.0 - 1e-6, "{:?} -> {:?}", direction, p);
+            } else {
+                assert!(l1 <= 1.0 + 1e-6, "{:?} -> {:?}", direction, p);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for direction in directions() {
+            let decoded = oct_decode(&oct_encode(&direction));
+            assert!(angle(&direction, &decoded) < 1e-5, "{:?} -> {:?}", direction, decoded);
+        }
+    }
+
+    #[test]
+    fn quantized_round_trip_error_is_bounded() {
+        let mut max_error: f32 = 0.0;
+        for direction in directions() {
+            let encoded = oct_encode(&direction);
+            let quantized = Vec2::new(quantize(encoded.x), quantize(encoded.y));
+            let error = angle(&direction, &oct_decode(&quantized));
+            assert!(error < U16_MAX_ERROR, "{:?} is off by {} radians", direction, error);
+            max_error = max_error.max(error);
+        }
+        // The quantization is actually being exercised
+        assert!(max_error > 1e-6);
+    }
+}
@@ -0,0 +1,444 @@
+use glium::backend::Facade;
+use glium::VertexBuffer;
+use memmap::Mmap;
+use mesh::{triangle_vertices, Mesh, MeshError};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::slice;
+use {Vec3, Vertex};
+
+// Layout of a cache file. Every value is little endian and the header is a multiple of 8 bytes
+// so the vertex data that follows it is suitably aligned once the file is mapped
+//
+//  0  magic          [u8; 4]
+//  4  version        u32
+//  8  flags          u32
+// 12  vertex count   u32
+// 16  index count    u32
+// 20  material len   u32
+// 24  bounds         [f32; 6]  (min xyz, max xyz)
+// 48  checksum       u64       (fnv-1a of the 48 bytes before it and everything after the header)
+// 56  vertices       [Vertex; vertex count]
+//     colours        [[f32; 4]; vertex count]  (only if FLAG_COLOURS is set)
+//     indices        [u32; index count]
+//     material       utf8 bytes
+pub const MAGIC: &[u8; 4] = b"RMSH";
+pub const VERSION: u32 = 2;
+pub const HEADER_SIZE: usize = 56;
+const CHECKSUM_OFFSET: usize = 48;
+const FLAG_COLOURS: u32 = 1;
+
+const VERTEX_FLOATS: usize = 14;
+
+#[derive(Clone, Debug)]
+pub struct CacheHeader {
+    pub version: u32,
+    pub vertex_count: usize,
+    pub index_count: usize,
+    pub has_colours: bool,
+    pub bounds: (Vec3, Vec3),
+    pub checksum: u64,
+    material_len: usize,
+}
+
+impl CacheHeader {
+    fn vertices_offset(&self) -> usize {
+        HEADER_SIZE
+    }
+
+    fn colours_offset(&self) -> usize {
+        self.vertices_offset() + self.vertex_count * mem::size_of::<Vertex>()
+    }
+
+    fn indices_offset(&self) -> usize {
+        let colours = if self.has_colours { self.vertex_count * 16 } else { 0 };
+        self.colours_offset() + colours
+    }
+
+    fn material_offset(&self) -> usize {
+        self.indices_offset() + self.index_count * 4
+    }
+
+    fn file_size(&self) -> usize {
+        self.material_offset() + self.material_len
+    }
+
+    // `file_size` for counts read from an untrusted file, None if it doesn't fit in a usize. Once
+    // this succeeds none of the offsets can overflow
+    fn checked_file_size(&self) -> Option<usize> {
+        let vertices = self.vertex_count.checked_mul(mem::size_of::<Vertex>())?;
+        let colours = if self.has_colours { self.vertex_count.checked_mul(16)? } else { 0 };
+        let indices = self.index_count.checked_mul(4)?;
+        HEADER_SIZE
+            .checked_add(vertices)?
+            .checked_add(colours)?
+            .checked_add(indices)?
+            .checked_add(self.material_len)
+    }
+}
+
+// A cache file mapped into memory. On little endian machines the vertex and index data is read
+// straight out of the mapping without copying
+pub struct CachedMesh {
+    pub header: CacheHeader,
+    map: Mmap,
+}
+
+impl CachedMesh {
+    // Maps and validates a cache file, returning an error if it is truncated, fails its
+    // checksum or was written by a different version
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CachedMesh, MeshError> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        let header = parse_header(&map)?;
+
+        let payload = &map[HEADER_SIZE..header.file_size()];
+        let checksum = fnv1a(&[&map[..CHECKSUM_OFFSET], payload]);
+        if checksum != header.checksum {
+            return Err(MeshError::Corrupt(format!(
+                "checksum mismatch, expected {:016x} found {:016x}",
+                header.checksum, checksum
+            )));
+        }
+
+        let cached = CachedMesh { header, map };
+        if cached.indices().iter().any(|&i| i as usize >= cached.header.vertex_count) {
+            return Err(MeshError::Corrupt("index out of range".to_string()));
+        }
+        Ok(cached)
+    }
+
+    pub fn vertices(&self) -> Cow<[Vertex]> {
+        let bytes = &self.map[self.header.vertices_offset()..self.header.colours_offset()];
+        if cfg!(target_endian = "little") {
+            // The header keeps the vertices 8 byte aligned and Vertex is repr(C) floats only
+            let vertices = unsafe {
+                slice::from_raw_parts(bytes.as_ptr() as *const Vertex, self.header.vertex_count)
+            };
+            Cow::Borrowed(vertices)
+        } else {
+            let floats = read_f32s(bytes);
+            Cow::Owned(floats.chunks(VERTEX_FLOATS).map(vertex_from_floats).collect())
+        }
+    }
+
+    pub fn colours(&self) -> Option<Vec<[f32; 4]>> {
+        if !self.header.has_colours {
+            return None;
+        }
+        let bytes = &self.map[self.header.colours_offset()..self.header.indices_offset()];
+        let floats = read_f32s(bytes);
+        Some(floats.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect())
+    }
+
+    pub fn indices(&self) -> Cow<[u32]> {
+        let bytes = &self.map[self.header.indices_offset()..self.header.material_offset()];
+        if cfg!(target_endian = "little") {
+            let indices =
+                unsafe { slice::from_raw_parts(bytes.as_ptr() as *const u32, self.header.index_count) };
+            Cow::Borrowed(indices)
+        } else {
+            Cow::Owned(bytes.chunks(4).map(read_u32).collect())
+        }
+    }
+
+    pub fn material(&self) -> Option<&str> {
+        let bytes = &self.map[self.header.material_offset()..self.header.file_size()];
+        match ::std::str::from_utf8(bytes) {
+            Ok("") | Err(_) => None,
+            Ok(material) => Some(material),
+        }
+    }
+
+    // Expands the indices so the mesh can be drawn with `NoIndices(TrianglesList)`, reading the
+    // vertices straight from the mapped file
+    pub fn triangle_vertices(&self) -> Vec<Vertex> {
+        triangle_vertices(&self.vertices(), &self.indices())
+    }
+
+    pub fn vertex_buffer<F: Facade>(&self, facade: &F) -> Result<VertexBuffer<Vertex>, Box<Error>> {
+        let buffer = VertexBuffer::new(facade, &self.triangle_vertices())?;
+        Ok(buffer)
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        Mesh {
+            vertices: self.vertices().into_owned(),
+            indices: self.indices().into_owned(),
+            colours: self.colours(),
+        }
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<CacheHeader, MeshError> {
+    if data.len() < HEADER_SIZE {
+        return Err(MeshError::Corrupt("file is smaller than the header".to_string()));
+    }
+    if &data[0..4] != MAGIC {
+        return Err(MeshError::Corrupt("not a mesh cache file".to_string()));
+    }
+
+    let version = read_u32(&data[4..]);
+    if version != VERSION {
+        return Err(MeshError::Outdated(version));
+    }
+
+    let bounds = read_f32s(&data[24..48]);
+    let header = CacheHeader {
+        version,
+        has_colours: read_u32(&data[8..]) & FLAG_COLOURS != 0,
+        vertex_count: read_u32(&data[12..]) as usize,
+        index_count: read_u32(&data[16..]) as usize,
+        material_len: read_u32(&data[20..]) as usize,
+        bounds: (
+            Vec3::new(bounds[0], bounds[1], bounds[2]),
+            Vec3::new(bounds[3], bounds[4], bounds[5]),
+        ),
+        checksum: read_u64(&data[CHECKSUM_OFFSET..]),
+    };
+
+    let file_size = header
+        .checked_file_size()
+        .ok_or_else(|| MeshError::Corrupt("element counts overflow".to_string()))?;
+    if data.len() < file_size {
+        return Err(MeshError::Corrupt(format!(
+            "file is truncated, expected {} bytes found {}",
+            file_size,
+            data.len()
+        )));
+    }
+    Ok(header)
+}
+
+pub fn write_cache<P: AsRef<Path>>(path: P, mesh: &Mesh, material: Option<&str>) -> Result<(), MeshError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode_cache(&mut writer, mesh, material)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn encode_cache<W: Write>(writer: &mut W, mesh: &Mesh, material: Option<&str>) -> Result<(), MeshError> {
+    let material = material.unwrap_or("");
+
+    let mut payload = Vec::with_capacity(
+        mesh.vertices.len() * mem::size_of::<Vertex>() + mesh.indices.len() * 4 + material.len(),
+    );
+    for vertex in &mesh.vertices {
+        for value in vertex_floats(vertex).iter() {
+            payload.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+    }
+    if let Some(colours) = &mesh.colours {
+        for value in colours.iter().flat_map(|c| c.iter()) {
+            payload.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+    }
+    for index in &mesh.indices {
+        payload.extend_from_slice(&index.to_le_bytes());
+    }
+    payload.extend_from_slice(material.as_bytes());
+
+    let flags = if mesh.colours.is_some() { FLAG_COLOURS } else { 0 };
+    let (min, max) = mesh.bounds();
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(MAGIC);
+    for value in &[
+        VERSION,
+        flags,
+        mesh.vertices.len() as u32,
+        mesh.indices.len() as u32,
+        material.len() as u32,
+    ] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in min.iter().chain(max.iter()) {
+        header.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+    let checksum = fnv1a(&[&header, &payload]);
+    header.extend_from_slice(&checksum.to_le_bytes());
+
+    writer.write_all(&header)?;
+    writer.write_all(&payload)?;
+
+    Ok(())
+}
+
+fn vertex_floats(vertex: &Vertex) -> [f32; VERTEX_FLOATS] {
+    let mut floats = [0.0; VERTEX_FLOATS];
+    floats[0..3].copy_from_slice(&vertex.position);
+    floats[3..6].copy_from_slice(&vertex.normal);
+    floats[6..9].copy_from_slice(&vertex.tangent);
+    floats[9..12].copy_from_slice(&vertex.bitangent);
+    floats[12..14].copy_from_slice(&vertex.tex_coord);
+    floats
+}
+
+fn vertex_from_floats(f: &[f32]) -> Vertex {
+    Vertex {
+        position: [f[0], f[1], f[2]],
+        normal: [f[3], f[4], f[5]],
+        tangent: [f[6], f[7], f[8]],
+        bitangent: [f[9], f[10], f[11]],
+        tex_coord: [f[12], f[13]],
+    }
+}
+
+// Hashes the parts as if they were one slice
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(array)
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks(4).map(|b| f32::from_bits(read_u32(b))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use Vec2;
+
+    fn triangle() -> Mesh {
+        Mesh::from_attributes(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            None,
+            Some(vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)]),
+            Some(vec![[1.0, 0.0, 0.0, 1.0]; 3]),
+            vec![0, 1, 2],
+        )
+    }
+
+    fn encoded() -> Vec<u8> {
+        let mut data = Vec::new();
+        encode_cache(&mut data, &triangle(), Some("brick")).unwrap();
+        data
+    }
+
+    // Writes `data` to a file of its own and opens it as a cache
+    fn open_bytes(name: &str, data: &[u8]) -> Result<CachedMesh, MeshError> {
+        let path: PathBuf = env::temp_dir().join(format!("mesh-cache-{}-{}.rmc", name, process::id()));
+        fs::write(&path, data).unwrap();
+        let result = CachedMesh::open(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn assert_corrupt(result: Result<CachedMesh, MeshError>) {
+        match result {
+            Err(MeshError::Corrupt(_)) => {}
+            Err(err) => panic!("expected a corrupt cache, got {}", err),
+            Ok(_) => panic!("expected a corrupt cache"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = triangle();
+        let cached = open_bytes("round-trip", &encoded()).unwrap();
+        assert_eq!(cached.header.vertex_count, 3);
+        assert_eq!(cached.material(), Some("brick"));
+        assert_eq!(cached.colours(), mesh.colours);
+        assert_eq!(&*cached.indices(), &mesh.indices[..]);
+        for (a, b) in cached.vertices().iter().zip(&mesh.vertices) {
+            assert_eq!(vertex_floats(a), vertex_floats(b));
+        }
+    }
+
+    #[test]
+    fn shared_vertices_are_expanded() {
+        // A quad, two triangles sharing an edge
+        let mesh = Mesh::from_attributes(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            None,
+            None,
+            None,
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        let mut data = Vec::new();
+        encode_cache(&mut data, &mesh, None).unwrap();
+        let cached = open_bytes("expanded", &data).unwrap();
+
+        let expanded = cached.triangle_vertices();
+        assert_eq!(expanded.len(), 6);
+        for (a, b) in expanded.iter().zip(&mesh.triangle_vertices()) {
+            assert_eq!(vertex_floats(a), vertex_floats(b));
+        }
+    }
+
+    #[test]
+    fn truncated_file() {
+        let data = encoded();
+        assert_corrupt(open_bytes("truncated", &data[..data.len() - 1]));
+        assert_corrupt(open_bytes("truncated-header", &data[..HEADER_SIZE - 1]));
+    }
+
+    #[test]
+    fn bit_flipped_payload() {
+        let mut data = encoded();
+        data[HEADER_SIZE + 5] ^= 0x10;
+        assert_corrupt(open_bytes("flipped-payload", &data));
+    }
+
+    #[test]
+    fn bit_flipped_header() {
+        // The minimum x of the bounds
+        let mut data = encoded();
+        data[24] ^= 0x01;
+        assert_corrupt(open_bytes("flipped-bounds", &data));
+        // The colour flag, which changes where the indices are but not the file size needed
+        let mut data = encoded();
+        data[8] ^= 0x01;
+        assert_corrupt(open_bytes("flipped-flags", &data));
+    }
+
+    #[test]
+    fn overflowing_counts() {
+        let mut data = encoded();
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_corrupt(open_bytes("overflow", &data));
+    }
+
+    #[test]
+    fn outdated_version() {
+        let mut data = encoded();
+        data[4..8].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        match open_bytes("outdated", &data) {
+            Err(MeshError::Outdated(version)) => assert_eq!(version, VERSION - 1),
+            _ => panic!("expected an outdated cache"),
+        }
+    }
+}
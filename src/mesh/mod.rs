@@ -0,0 +1,280 @@
+pub mod cache;
+pub mod ply;
+pub mod stl;
+
+use glium::backend::Facade;
+use glium::VertexBuffer;
+use math::OrthoBasis;
+use na::{dot, norm};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use {Vec2, Vec3, Vertex};
+
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    Parse(String),
+    Unsupported(String),
+    Corrupt(String),
+    Outdated(u32),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::Io(err) => write!(f, "io error: {}", err),
+            MeshError::Parse(msg) => write!(f, "parse error: {}", msg),
+            MeshError::Unsupported(msg) => write!(f, "unsupported: {}", msg),
+            MeshError::Corrupt(msg) => write!(f, "corrupt mesh cache: {}", msg),
+            MeshError::Outdated(version) => write!(
+                f,
+                "mesh cache version {} is outdated, expected {}",
+                version,
+                cache::VERSION
+            ),
+        }
+    }
+}
+
+impl Error for MeshError {
+    fn description(&self) -> &str {
+        match self {
+            MeshError::Io(_) => "io error",
+            MeshError::Parse(_) => "parse error",
+            MeshError::Unsupported(_) => "unsupported mesh feature",
+            MeshError::Corrupt(_) => "corrupt mesh cache",
+            MeshError::Outdated(_) => "outdated mesh cache",
+        }
+    }
+}
+
+impl From<io::Error> for MeshError {
+    fn from(err: io::Error) -> MeshError {
+        MeshError::Io(err)
+    }
+}
+
+pub fn parse_error<T, S: Into<String>>(msg: S) -> Result<T, MeshError> {
+    Err(MeshError::Parse(msg.into()))
+}
+
+// Loads a mesh choosing the reader from the file extension
+pub fn read_mesh<P: AsRef<Path>>(path: P) -> Result<Mesh, MeshError> {
+    let path = path.as_ref();
+    match extension(path).as_ref().map(|ext| ext.as_str()) {
+        Some("ply") => ply::read_ply(path),
+        Some("stl") => stl::read_stl(path),
+        Some("rmc") => Ok(cache::CachedMesh::open(path)?.to_mesh()),
+        _ => Err(MeshError::Unsupported(format!(
+            "no mesh reader for {}",
+            path.display()
+        ))),
+    }
+}
+
+// Loads a mesh into a buffer for `NoIndices(TrianglesList)`. Cache files are uploaded straight
+// from the mapped file instead of being copied into a `Mesh` first
+pub fn read_vertex_buffer<F: Facade, P: AsRef<Path>>(
+    facade: &F,
+    path: P,
+) -> Result<VertexBuffer<Vertex>, Box<Error>> {
+    let path = path.as_ref();
+    match extension(path).as_ref().map(|ext| ext.as_str()) {
+        Some("rmc") => cache::CachedMesh::open(path)?.vertex_buffer(facade),
+        _ => read_mesh(path)?.vertex_buffer(facade),
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+}
+
+// Indexed triangle mesh with optional per-vertex colour. Every three indices make a triangle
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub colours: Option<Vec<[f32; 4]>>,
+}
+
+impl Mesh {
+    // Builds a mesh from raw attributes, generating smooth normals and planar uvs if they are
+    // missing. Tangents and bitangents are always generated from the uvs
+    pub fn from_attributes(
+        positions: Vec<Vec3>,
+        normals: Option<Vec<Vec3>>,
+        tex_coords: Option<Vec<Vec2>>,
+        colours: Option<Vec<[f32; 4]>>,
+        indices: Vec<u32>,
+    ) -> Mesh {
+        let normals = normals.unwrap_or_else(|| generate_normals(&positions, &indices));
+        let tex_coords = tex_coords.unwrap_or_else(|| generate_tex_coords(&positions));
+        let (tangents, bitangents) = generate_tangents(&positions, &tex_coords, &indices);
+
+        let vertices = (0..positions.len())
+            .map(|i| {
+                let basis = orthonormal_basis(normals[i], tangents[i], bitangents[i]);
+                Vertex {
+                    position: *positions[i].as_ref(),
+                    normal: *basis.v1.as_ref(),
+                    tangent: *basis.v2.as_ref(),
+                    bitangent: *basis.v3.as_ref(),
+                    tex_coord: *tex_coords[i].as_ref(),
+                }
+            })
+            .collect();
+
+        Mesh {
+            vertices,
+            indices,
+            colours,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        bounds(self.vertices.iter().map(|v| Vec3::new(v.position[0], v.position[1], v.position[2])))
+    }
+
+    // Expands the indices so the mesh can be drawn with `NoIndices(TrianglesList)`
+    pub fn triangle_vertices(&self) -> Vec<Vertex> {
+        triangle_vertices(&self.vertices, &self.indices)
+    }
+
+    pub fn vertex_buffer<F: Facade>(&self, facade: &F) -> Result<VertexBuffer<Vertex>, Box<Error>> {
+        let buffer = VertexBuffer::new(facade, &self.triangle_vertices())?;
+        Ok(buffer)
+    }
+}
+
+// One vertex per index, three per triangle
+pub fn triangle_vertices(vertices: &[Vertex], indices: &[u32]) -> Vec<Vertex> {
+    indices.iter().map(|&i| vertices[i as usize]).collect()
+}
+
+pub fn bounds<I: Iterator<Item = Vec3>>(points: I) -> (Vec3, Vec3) {
+    let mut min = Vec3::from_element(::std::f32::INFINITY);
+    let mut max = Vec3::from_element(::std::f32::NEG_INFINITY);
+
+    for point in points {
+        for i in 0..3 {
+            min[i] = min[i].min(point[i]);
+            max[i] = max[i].max(point[i]);
+        }
+    }
+
+    (min, max)
+}
+
+// Area weighted smooth normals
+pub fn generate_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::zeros(); positions.len()];
+
+    for tri in indices.chunks(3).filter(|tri| tri.len() == 3) {
+        let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+        let face = (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+        normals[a] += face;
+        normals[b] += face;
+        normals[c] += face;
+    }
+
+    normals
+        .into_iter()
+        .map(|n| normalize_or(n, Vec3::new(0.0, 0.0, 1.0)))
+        .collect()
+}
+
+// Placeholder uvs made by projecting onto the two largest axes of the bounding box
+pub fn generate_tex_coords(positions: &[Vec3]) -> Vec<Vec2> {
+    let (min, max) = bounds(positions.iter().cloned());
+    let extent = max - min;
+
+    let mut axes = [0, 1, 2];
+    axes.sort_by(|&a, &b| extent[b].partial_cmp(&extent[a]).unwrap_or(::std::cmp::Ordering::Equal));
+    let (u_axis, v_axis) = (axes[0], axes[1]);
+    let u_scale = if extent[u_axis] > 0.0 { 1.0 / extent[u_axis] } else { 0.0 };
+    let v_scale = if extent[v_axis] > 0.0 { 1.0 / extent[v_axis] } else { 0.0 };
+
+    positions
+        .iter()
+        .map(|p| {
+            Vec2::new(
+                (p[u_axis] - min[u_axis]) * u_scale,
+                (p[v_axis] - min[v_axis]) * v_scale,
+            )
+        })
+        .collect()
+}
+
+// Accumulates per triangle tangents onto each vertex. Unlike `tangent_space_from` this skips
+// triangles with degenerate uvs instead of panicking
+pub fn generate_tangents(
+    positions: &[Vec3],
+    tex_coords: &[Vec2],
+    indices: &[u32],
+) -> (Vec<Vec3>, Vec<Vec3>) {
+    let mut tangents = vec![Vec3::zeros(); positions.len()];
+    let mut bitangents = vec![Vec3::zeros(); positions.len()];
+
+    for tri in indices.chunks(3).filter(|tri| tri.len() == 3) {
+        let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+        let v1 = positions[b] - positions[a];
+        let v2 = positions[c] - positions[a];
+        let t1 = tex_coords[b] - tex_coords[a];
+        let t2 = tex_coords[c] - tex_coords[a];
+
+        let det = t1.x * t2.y - t1.y * t2.x;
+        if det.abs() < 1e-12 {
+            continue;
+        }
+        let inv_det = 1.0 / det;
+        let tangent = (v1 * t2.y - v2 * t1.y) * inv_det;
+        let bitangent = (v2 * t1.x - v1 * t2.x) * inv_det;
+
+        for &i in &[a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    (tangents, bitangents)
+}
+
+// Gram schmidt on {normal, tangent, bitangent}, picking an arbitrary tangent if the
+// accumulated one is missing or parallel to the normal
+fn orthonormal_basis(normal: Vec3, tangent: Vec3, bitangent: Vec3) -> OrthoBasis {
+    let normal = normalize_or(normal, Vec3::new(0.0, 0.0, 1.0));
+    let tangent = tangent - dot(&tangent, &normal) * normal;
+    let tangent = if norm(&tangent) > 1e-6 {
+        tangent
+    } else if normal.x.abs() < 0.9 {
+        normal.cross(&Vec3::new(1.0, 0.0, 0.0))
+    } else {
+        normal.cross(&Vec3::new(0.0, 1.0, 0.0))
+    };
+    let unit_tangent = tangent / norm(&tangent);
+    let residual =
+        bitangent - dot(&bitangent, &normal) * normal - dot(&bitangent, &unit_tangent) * unit_tangent;
+    let bitangent = if norm(&residual) > 1e-6 {
+        bitangent
+    } else {
+        normal.cross(&unit_tangent)
+    };
+
+    OrthoBasis::from_basis(normal, tangent, bitangent)
+}
+
+fn normalize_or(v: Vec3, fallback: Vec3) -> Vec3 {
+    let length = norm(&v);
+    if length > 1e-12 && length.is_finite() {
+        v / length
+    } else {
+        fallback
+    }
+}
@@ -0,0 +1,562 @@
+use mesh::{parse_error, Mesh, MeshError};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::str;
+use {Vec2, Vec3};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, MeshError> {
+        let ty = match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return parse_error(format!("unknown ply property type '{}'", name)),
+        };
+        Ok(ty)
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    // Scale used to bring integer colour channels into 0..1
+    fn colour_scale(&self) -> f64 {
+        match self {
+            ScalarType::U8 => 1.0 / 255.0,
+            ScalarType::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+enum PropertyKind {
+    Scalar(ScalarType),
+    List(ScalarType, ScalarType),
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|p| names.iter().any(|name| *name == p.name))
+    }
+}
+
+struct Header {
+    format: PlyFormat,
+    elements: Vec<Element>,
+}
+
+// Pulls values out of the body of a ply file, either as whitespace separated text or as packed
+// binary scalars
+struct BodyReader<'a> {
+    format: PlyFormat,
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> BodyReader<'a> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, MeshError> {
+        match self.format {
+            PlyFormat::Ascii => self.read_ascii(),
+            PlyFormat::BinaryLittleEndian => self.read_binary(ty, false),
+            PlyFormat::BinaryBigEndian => self.read_binary(ty, true),
+        }
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, MeshError> {
+        while self.cursor < self.data.len() && (self.data[self.cursor] as char).is_whitespace() {
+            self.cursor += 1;
+        }
+        let start = self.cursor;
+        while self.cursor < self.data.len() && !(self.data[self.cursor] as char).is_whitespace() {
+            self.cursor += 1;
+        }
+        if start == self.cursor {
+            return parse_error("unexpected end of ply data");
+        }
+
+        let token = str::from_utf8(&self.data[start..self.cursor])
+            .map_err(|_| MeshError::Parse("invalid utf8 in ply data".to_string()))?;
+        token
+            .parse::<f64>()
+            .map_err(|_| MeshError::Parse(format!("invalid ply value '{}'", token)))
+    }
+
+    fn read_binary(&mut self, ty: ScalarType, big_endian: bool) -> Result<f64, MeshError> {
+        let size = ty.size();
+        if self.cursor + size > self.data.len() {
+            return parse_error("unexpected end of ply data");
+        }
+
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.cursor..self.cursor + size]);
+        self.cursor += size;
+        if big_endian {
+            bytes[..size].reverse();
+        }
+
+        // Bytes are now little endian
+        let value = match ty {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F32 => {
+                f32::from_bits(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])) as f64
+            }
+            ScalarType::F64 => f64::from_bits(u64::from_le_bytes(bytes)),
+        };
+        Ok(value)
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<(Header, usize), MeshError> {
+    let marker = b"end_header";
+    let end = data
+        .windows(marker.len())
+        .position(|w| w == marker)
+        .ok_or_else(|| MeshError::Parse("ply header has no end_header".to_string()))?;
+    // The body starts on the line after end_header
+    let body_start = data[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|i| end + i + 1)
+        .unwrap_or(data.len());
+
+    let text = str::from_utf8(&data[..end])
+        .map_err(|_| MeshError::Parse("ply header is not valid utf8".to_string()))?;
+    let mut lines = text.lines().map(|line| line.trim());
+    if lines.next() != Some("ply") {
+        return parse_error("missing ply magic number");
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return parse_error(format!("unknown ply format '{}'", name)),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| MeshError::Parse(format!("invalid element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, item_ty, name] => {
+                let kind = PropertyKind::List(ScalarType::parse(count_ty)?, ScalarType::parse(item_ty)?);
+                push_property(&mut elements, name, kind)?;
+            }
+            ["property", ty, name] => {
+                let kind = PropertyKind::Scalar(ScalarType::parse(ty)?);
+                push_property(&mut elements, name, kind)?;
+            }
+            _ => return parse_error(format!("invalid ply header line '{}'", line)),
+        }
+    }
+
+    let format = format.ok_or_else(|| MeshError::Parse("ply header has no format".to_string()))?;
+    Ok((Header { format, elements }, body_start))
+}
+
+fn push_property(elements: &mut Vec<Element>, name: &str, kind: PropertyKind) -> Result<(), MeshError> {
+    match elements.last_mut() {
+        Some(element) => {
+            element.properties.push(Property {
+                name: name.to_string(),
+                kind,
+            });
+            Ok(())
+        }
+        None => parse_error("ply property declared before any element"),
+    }
+}
+
+// Reads an ascii or binary ply file. Faces with more than three vertices are fan triangulated.
+// Normals and uvs are generated if the file does not provide them
+pub fn read_ply<P: AsRef<Path>>(path: P) -> Result<Mesh, MeshError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    parse_ply(&data)
+}
+
+pub fn parse_ply(data: &[u8]) -> Result<Mesh, MeshError> {
+    let (header, body_start) = parse_header(data)?;
+    let mut reader = BodyReader {
+        format: header.format,
+        data: &data[body_start..],
+        cursor: 0,
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut colours = Vec::new();
+    let mut indices = Vec::new();
+    let mut has_normals = false;
+    let mut has_tex_coords = false;
+    let mut has_colours = false;
+
+    for element in &header.elements {
+        let position = [
+            element.property_index(&["x"]),
+            element.property_index(&["y"]),
+            element.property_index(&["z"]),
+        ];
+        let normal = [
+            element.property_index(&["nx"]),
+            element.property_index(&["ny"]),
+            element.property_index(&["nz"]),
+        ];
+        let tex_coord = [
+            element.property_index(&["u", "s", "texture_u", "texture_s"]),
+            element.property_index(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let colour = [
+            element.property_index(&["red", "r"]),
+            element.property_index(&["green", "g"]),
+            element.property_index(&["blue", "b"]),
+            element.property_index(&["alpha", "a"]),
+        ];
+        let face = element.property_index(&["vertex_indices", "vertex_index"]);
+
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        if is_vertex {
+            if position.iter().any(|p| p.is_none()) {
+                return parse_error("ply vertex element has no x, y and z properties");
+            }
+            has_normals = normal.iter().all(|p| p.is_some());
+            has_tex_coords = tex_coord.iter().all(|p| p.is_some());
+            has_colours = colour[..3].iter().all(|p| p.is_some());
+        }
+
+        let mut values = vec![0.0; element.properties.len()];
+        let mut list = Vec::new();
+
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(ty) => values[i] = reader.read(ty)? * colour_scale(ty, i, &colour),
+                    PropertyKind::List(count_ty, item_ty) => {
+                        let count = reader.read(count_ty)? as usize;
+                        let is_face_list = is_face && Some(i) == face;
+                        if is_face_list {
+                            list.clear();
+                        }
+                        for _ in 0..count {
+                            let value = reader.read(item_ty)?;
+                            if is_face_list {
+                                list.push(value as u32);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                let get = |index: Option<usize>| index.map(|i| values[i] as f32).unwrap_or(0.0);
+                positions.push(Vec3::new(get(position[0]), get(position[1]), get(position[2])));
+                if has_normals {
+                    normals.push(Vec3::new(get(normal[0]), get(normal[1]), get(normal[2])));
+                }
+                if has_tex_coords {
+                    tex_coords.push(Vec2::new(get(tex_coord[0]), get(tex_coord[1])));
+                }
+                if has_colours {
+                    let alpha = colour[3].map(|i| values[i] as f32).unwrap_or(1.0);
+                    colours.push([get(colour[0]), get(colour[1]), get(colour[2]), alpha]);
+                }
+            } else if is_face && list.len() >= 3 {
+                for i in 1..list.len() - 1 {
+                    indices.extend_from_slice(&[list[0], list[i], list[i + 1]]);
+                }
+            }
+        }
+    }
+
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        return parse_error(format!("ply face references missing vertex {}", index));
+    }
+
+    Ok(Mesh::from_attributes(
+        positions,
+        if has_normals { Some(normals) } else { None },
+        if has_tex_coords { Some(tex_coords) } else { None },
+        if has_colours { Some(colours) } else { None },
+        indices,
+    ))
+}
+
+fn colour_scale(ty: ScalarType, property: usize, colour: &[Option<usize>; 4]) -> f64 {
+    if colour.contains(&Some(property)) {
+        ty.colour_scale()
+    } else {
+        1.0
+    }
+}
+
+pub fn write_ply<P: AsRef<Path>>(path: P, mesh: &Mesh, format: PlyFormat) -> Result<(), MeshError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode_ply(&mut writer, mesh, format)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Writes positions, normals, uvs and colours (if the mesh has them) along with triangle faces
+pub fn encode_ply<W: Write>(writer: &mut W, mesh: &Mesh, format: PlyFormat) -> Result<(), MeshError> {
+    let format_name = match format {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+        PlyFormat::BinaryBigEndian => "binary_big_endian",
+    };
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format {} 1.0", format_name)?;
+    writeln!(writer, "element vertex {}", mesh.vertices.len())?;
+    for name in &["x", "y", "z", "nx", "ny", "nz", "u", "v"] {
+        writeln!(writer, "property float {}", name)?;
+    }
+    if mesh.colours.is_some() {
+        for name in &["red", "green", "blue", "alpha"] {
+            writeln!(writer, "property uchar {}", name)?;
+        }
+    }
+    writeln!(writer, "element face {}", mesh.triangle_count())?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    for (i, vertex) in mesh.vertices.iter().enumerate() {
+        let floats = [
+            vertex.position[0],
+            vertex.position[1],
+            vertex.position[2],
+            vertex.normal[0],
+            vertex.normal[1],
+            vertex.normal[2],
+            vertex.tex_coord[0],
+            vertex.tex_coord[1],
+        ];
+        let colour = mesh.colours.as_ref().map(|colours| {
+            let c = colours[i];
+            [to_u8(c[0]), to_u8(c[1]), to_u8(c[2]), to_u8(c[3])]
+        });
+
+        match format {
+            PlyFormat::Ascii => {
+                let mut line: Vec<String> = floats.iter().map(|f| f.to_string()).collect();
+                if let Some(colour) = colour {
+                    line.extend(colour.iter().map(|c| c.to_string()));
+                }
+                writeln!(writer, "{}", line.join(" "))?;
+            }
+            _ => {
+                let big_endian = format == PlyFormat::BinaryBigEndian;
+                for value in &floats {
+                    write_u32(writer, value.to_bits(), big_endian)?;
+                }
+                if let Some(colour) = colour {
+                    writer.write_all(&colour)?;
+                }
+            }
+        }
+    }
+
+    for tri in mesh.indices.chunks(3).filter(|tri| tri.len() == 3) {
+        match format {
+            PlyFormat::Ascii => writeln!(writer, "3 {} {} {}", tri[0], tri[1], tri[2])?,
+            _ => {
+                let big_endian = format == PlyFormat::BinaryBigEndian;
+                writer.write_all(&[3])?;
+                for &index in tri {
+                    write_u32(writer, index, big_endian)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32, big_endian: bool) -> Result<(), MeshError> {
+    let bytes = if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    };
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad() -> Mesh {
+        Mesh::from_attributes(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.5, 0.0, 0.0),
+                Vec3::new(1.5, 2.0, 0.25),
+                Vec3::new(0.0, 2.0, 0.25),
+            ],
+            Some(vec![Vec3::new(0.0, -0.124, 0.992); 4]),
+            Some(vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(1.0, 1.0),
+                Vec2::new(0.0, 1.0),
+            ]),
+            Some(vec![
+                [1.0, 0.0, 0.0, 1.0],
+                [0.0, 1.0, 0.0, 1.0],
+                [0.0, 0.0, 1.0, 0.5],
+                [0.2, 0.4, 0.6, 0.0],
+            ]),
+            vec![0, 1, 2, 0, 2, 3],
+        )
+    }
+
+    fn assert_close(a: &[f32], b: &[f32], tolerance: f32) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() <= tolerance, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn round_trip(format: PlyFormat) {
+        let mesh = quad();
+        let mut data = Vec::new();
+        encode_ply(&mut data, &mesh, format).unwrap();
+        let read = parse_ply(&data).unwrap();
+
+        assert_eq!(read.indices, mesh.indices);
+        assert_eq!(read.vertices.len(), mesh.vertices.len());
+        for (a, b) in read.vertices.iter().zip(&mesh.vertices) {
+            assert_close(&a.position, &b.position, 0.0);
+            assert_close(&a.normal, &b.normal, 1e-6);
+            assert_close(&a.tex_coord, &b.tex_coord, 0.0);
+        }
+        // Colours are stored as 8 bit channels
+        let colours = read.colours.unwrap();
+        for (a, b) in colours.iter().zip(mesh.colours.as_ref().unwrap()) {
+            assert_close(a, b, 0.5 / 255.0 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        round_trip(PlyFormat::Ascii);
+    }
+
+    #[test]
+    fn binary_little_endian_round_trip() {
+        round_trip(PlyFormat::BinaryLittleEndian);
+    }
+
+    #[test]
+    fn binary_big_endian_round_trip() {
+        round_trip(PlyFormat::BinaryBigEndian);
+    }
+
+    #[test]
+    fn quads_are_triangulated() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        let mesh = parse_ply(data).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert!(mesh.colours.is_none());
+    }
+
+    fn assert_parse_error(data: &[u8]) {
+        match parse_ply(data) {
+            Err(MeshError::Parse(_)) => {}
+            Err(err) => panic!("expected a parse error, got {}", err),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        // No magic number
+        assert_parse_error(b"format ascii 1.0\nelement vertex 0\nend_header\n");
+        // No format line
+        assert_parse_error(b"ply\nelement vertex 0\nproperty float x\nend_header\n");
+        // Unknown format
+        assert_parse_error(b"ply\nformat binary_middle_endian 1.0\nend_header\n");
+        // Property outside of an element
+        assert_parse_error(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n");
+        // Unknown property type
+        assert_parse_error(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n");
+        // Invalid element count
+        assert_parse_error(b"ply\nformat ascii 1.0\nelement vertex many\nend_header\n");
+        // Missing end of header
+        assert_parse_error(b"ply\nformat ascii 1.0\nelement vertex 0\n");
+    }
+
+    #[test]
+    fn truncated_body_is_an_error() {
+        let mut data = Vec::new();
+        encode_ply(&mut data, &quad(), PlyFormat::BinaryLittleEndian).unwrap();
+        data.truncate(data.len() - 5);
+        assert!(parse_ply(&data).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use mesh::{parse_error, Mesh, MeshError};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::str;
+use Vec3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+// Reads an ascii or binary stl file. Stl has no shared vertices so every triangle gets its own
+// three vertices using the facet normal, or a computed one if the facet normal is zero
+pub fn read_stl<P: AsRef<Path>>(path: P) -> Result<Mesh, MeshError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    parse_stl(&data)
+}
+
+pub fn parse_stl(data: &[u8]) -> Result<Mesh, MeshError> {
+    let facets = if is_binary(data) {
+        parse_binary(data)?
+    } else {
+        parse_ascii(data)?
+    };
+
+    let mut positions = Vec::with_capacity(facets.len() * 3);
+    let mut normals = Vec::with_capacity(facets.len() * 3);
+    for (normal, corners) in facets {
+        let computed = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+        let normal = if normal.norm_squared() > 0.0 { normal } else { computed };
+        for corner in &corners {
+            positions.push(*corner);
+            normals.push(normal.normalize());
+        }
+    }
+    let indices = (0..positions.len() as u32).collect();
+
+    Ok(Mesh::from_attributes(positions, Some(normals), None, None, indices))
+}
+
+// Binary files may also start with "solid" so check the size matches the triangle count
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < 84 {
+        return false;
+    }
+    let count = read_u32(&data[80..84]) as usize;
+    data.len() == 84 + count * 50 || !data.starts_with(b"solid")
+}
+
+fn parse_binary(data: &[u8]) -> Result<Vec<(Vec3, [Vec3; 3])>, MeshError> {
+    let count = read_u32(&data[80..84]) as usize;
+    if data.len() < 84 + count * 50 {
+        return parse_error(format!("binary stl is truncated, expected {} triangles", count));
+    }
+
+    let facets = data[84..84 + count * 50]
+        .chunks(50)
+        .map(|facet| {
+            let vec = |offset: usize| {
+                Vec3::new(
+                    read_f32(&facet[offset..]),
+                    read_f32(&facet[offset + 4..]),
+                    read_f32(&facet[offset + 8..]),
+                )
+            };
+            (vec(0), [vec(12), vec(24), vec(36)])
+        })
+        .collect();
+    Ok(facets)
+}
+
+fn parse_ascii(data: &[u8]) -> Result<Vec<(Vec3, [Vec3; 3])>, MeshError> {
+    let text = str::from_utf8(data).map_err(|_| MeshError::Parse("ascii stl is not valid utf8".to_string()))?;
+    let mut words = text.split_whitespace();
+    let mut facets = Vec::new();
+    let mut normal = Vec3::zeros();
+    let mut corners = Vec::with_capacity(3);
+
+    while let Some(word) = words.next() {
+        match word {
+            "normal" => normal = read_ascii_vec(&mut words)?,
+            "vertex" => corners.push(read_ascii_vec(&mut words)?),
+            "endfacet" => {
+                if corners.len() != 3 {
+                    return parse_error(format!("stl facet has {} vertices, expected 3", corners.len()));
+                }
+                facets.push((normal, [corners[0], corners[1], corners[2]]));
+                normal = Vec3::zeros();
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(facets)
+}
+
+fn read_ascii_vec<'a, I: Iterator<Item = &'a str>>(words: &mut I) -> Result<Vec3, MeshError> {
+    let mut values = [0.0; 3];
+    for value in values.iter_mut() {
+        let word = match words.next() {
+            Some(word) => word,
+            None => return parse_error("unexpected end of stl data"),
+        };
+        *value = word
+            .parse()
+            .map_err(|_| MeshError::Parse(format!("invalid stl value '{}'", word)))?;
+    }
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_f32(bytes: &[u8]) -> f32 {
+    f32::from_bits(read_u32(bytes))
+}
+
+pub fn write_stl<P: AsRef<Path>>(path: P, mesh: &Mesh, format: StlFormat) -> Result<(), MeshError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode_stl(&mut writer, mesh, format)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Writes each triangle with its face normal. Uvs, tangents and colours are lost
+pub fn encode_stl<W: Write>(writer: &mut W, mesh: &Mesh, format: StlFormat) -> Result<(), MeshError> {
+    let position = |index: u32| {
+        let p = mesh.vertices[index as usize].position;
+        Vec3::new(p[0], p[1], p[2])
+    };
+    let facets = mesh.indices.chunks(3).filter(|tri| tri.len() == 3).map(|tri| {
+        let corners = [position(tri[0]), position(tri[1]), position(tri[2])];
+        let normal = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+        let normal = if normal.norm_squared() > 0.0 { normal.normalize() } else { normal };
+        (normal, corners)
+    });
+
+    match format {
+        StlFormat::Ascii => {
+            writeln!(writer, "solid mesh")?;
+            for (normal, corners) in facets {
+                writeln!(writer, "facet normal {} {} {}", normal.x, normal.y, normal.z)?;
+                writeln!(writer, "outer loop")?;
+                for corner in &corners {
+                    writeln!(writer, "vertex {} {} {}", corner.x, corner.y, corner.z)?;
+                }
+                writeln!(writer, "endloop")?;
+                writeln!(writer, "endfacet")?;
+            }
+            writeln!(writer, "endsolid mesh")?;
+        }
+        StlFormat::Binary => {
+            let mut header = [0u8; 80];
+            let name = b"binary stl";
+            header[..name.len()].copy_from_slice(name);
+            writer.write_all(&header)?;
+            writer.write_all(&(mesh.triangle_count() as u32).to_le_bytes())?;
+
+            for (normal, corners) in facets {
+                for vec in [normal, corners[0], corners[1], corners[2]].iter() {
+                    for i in 0..3 {
+                        writer.write_all(&vec[i].to_bits().to_le_bytes())?;
+                    }
+                }
+                writer.write_all(&[0, 0])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tetrahedron() -> Mesh {
+        Mesh::from_attributes(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ],
+            None,
+            None,
+            None,
+            vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+        )
+    }
+
+    fn face_normal(corners: &[[f32; 3]]) -> Vec3 {
+        let p = |i: usize| Vec3::new(corners[i][0], corners[i][1], corners[i][2]);
+        (p(1) - p(0)).cross(&(p(2) - p(0))).normalize()
+    }
+
+    fn round_trip(format: StlFormat) {
+        let mesh = tetrahedron();
+        let mut data = Vec::new();
+        encode_stl(&mut data, &mesh, format).unwrap();
+        let read = parse_stl(&data).unwrap();
+
+        // Every triangle gets its own vertices, in the original order
+        assert_eq!(read.triangle_count(), mesh.triangle_count());
+        assert_eq!(read.indices, (0..read.vertices.len() as u32).collect::<Vec<_>>());
+        for (i, &index) in mesh.indices.iter().enumerate() {
+            assert_eq!(read.vertices[i].position, mesh.vertices[index as usize].position);
+        }
+        // Vertices take the facet normal
+        for triangle in read.vertices.chunks(3) {
+            let corners: Vec<[f32; 3]> = triangle.iter().map(|v| v.position).collect();
+            let expected = face_normal(&corners);
+            for vertex in triangle {
+                for i in 0..3 {
+                    assert!((vertex.normal[i] - expected[i]).abs() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        round_trip(StlFormat::Ascii);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        round_trip(StlFormat::Binary);
+    }
+
+    #[test]
+    fn binary_starting_with_solid_is_detected() {
+        let mut data = Vec::new();
+        encode_stl(&mut data, &tetrahedron(), StlFormat::Binary).unwrap();
+        data[..5].copy_from_slice(b"solid");
+        assert_eq!(parse_stl(&data).unwrap().triangle_count(), 4);
+    }
+
+    #[test]
+    fn truncated_binary_header_is_an_error() {
+        // Claims 5 triangles but has none
+        let mut data = vec![0u8; 84];
+        data[80..84].copy_from_slice(&5u32.to_le_bytes());
+        match parse_stl(&data) {
+            Err(MeshError::Parse(_)) => {}
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn malformed_ascii_is_an_error() {
+        let two_vertices = b"solid s\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+endloop\nendfacet\nendsolid s\n";
+        assert!(parse_stl(two_vertices).is_err());
+        let bad_number = b"solid s\nfacet normal 0 0 one\nendsolid s\n";
+        assert!(parse_stl(bad_number).is_err());
+    }
+}
@@ -0,0 +1,662 @@
+use camera::PCamera;
+use gbuffer::GBuffer;
+use glium::backend::glutin::Display;
+use glium::backend::{Context, Facade};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::index::{NoIndices, PrimitiveType::TrianglesList};
+use glium::texture::{
+    MipmapsOption::{EmptyMipmaps, NoMipmap}, Texture2d, UncompressedFloatFormat,
+};
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerWrapFunction, UniformValue,
+    Uniforms,
+};
+use glium::vertex::EmptyVertexAttributes;
+use glium::{Program, Surface};
+use material::{MaterialParams, UniformsChain};
+use preprocess::{flags, Defines};
+use program::fullscreen_program;
+use ssao::Ssao;
+use std::any::Any;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::Instant;
+
+// What a pass can sample: the output of the previous pass as `source_tex`, the light buffer as
+// `light_tex`, the G-buffer attachments under their usual names and the occlusion as `ao_tex`
+pub struct PostInputs<'t> {
+    pub source: &'t Texture2d,
+    pub gbuffer: &'t GBuffer<'t>,
+    // The occlusion `LightingPass::draw_ambient` was drawn with, if any
+    pub occlusion: Option<&'t Ssao>,
+    pub camera: &'t PCamera,
+}
+
+impl<'t> Uniforms for PostInputs<'t> {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        output("source_tex", UniformValue::Texture2d(self.source, None));
+        output("light_tex", UniformValue::Texture2d(&self.gbuffer.light, None));
+        for attachment in &self.gbuffer.attachments {
+            output(&attachment.sampler, UniformValue::Texture2d(&attachment.texture, None));
+        }
+        output("depth_tex", UniformValue::DepthTexture2d(&self.gbuffer.depth, None));
+        if let Some(ssao) = self.occlusion {
+            ssao.visit_values(output);
+        }
+    }
+}
+
+// A full-screen pass in a `PostChain`
+pub trait PostPass {
+    fn name(&self) -> &str;
+
+    // Draws the pass into `target`, which is never one of the inputs
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>>;
+
+    // Lets `PostChain::get_mut` hand back the concrete pass. Implement as `{ self }`
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+// Draws a full-screen triangle
+pub fn draw_fullscreen<S: Surface, U: Uniforms>(
+    surface: &mut S,
+    program: &Program,
+    uniforms: &U,
+) -> Result<(), Box<Error>> {
+    surface.draw(
+        EmptyVertexAttributes { len: 3 },
+        NoIndices(TrianglesList),
+        program,
+        uniforms,
+        &Default::default(),
+    )?;
+    Ok(())
+}
+
+// A pass made from a fragment shader and its parameters, for passes that need no CPU side logic
+pub struct ShaderPass {
+    name: String,
+    program: Program,
+    pub params: MaterialParams,
+}
+
+impl ShaderPass {
+    pub fn new(name: &str, program: Program, params: MaterialParams) -> ShaderPass {
+        ShaderPass {
+            name: name.to_string(),
+            program,
+            params,
+        }
+    }
+}
+
+impl PostPass for ShaderPass {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>> {
+        draw_fullscreen(target, &self.program, &UniformsChain(inputs, &self.params))
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+    // Hable's filmic curve, with the linear value that maps to white
+    Uncharted2 { white_point: f32 },
+}
+
+impl ToneMapOperator {
+    // The `tonemap_operator` uniform of tonemap.glsl
+    fn index(&self) -> i32 {
+        match self {
+            ToneMapOperator::Reinhard => 0,
+            ToneMapOperator::Aces => 1,
+            ToneMapOperator::Uncharted2 { .. } => 2,
+        }
+    }
+}
+
+// Maps the HDR light buffer into 0..1 using the exposure settings of the camera. With auto
+// exposure the log luminance of the frame is averaged down a mip chain and the result is eased
+// towards over time, all on the GPU
+pub struct Tonemap {
+    context: Rc<Context>,
+    program: Program,
+    luminance_program: Program,
+    adapt_program: Program,
+    pub operator: ToneMapOperator,
+    luminance: Option<Texture2d>,
+    // 1x1 ping-pong targets for the adapted luminance, the current one is `adapted[current]`
+    adapted: [Texture2d; 2],
+    current: usize,
+    last_frame: Option<Instant>,
+}
+
+impl Tonemap {
+    pub fn new(display: &Display) -> Result<Tonemap, Box<Error>> {
+        Ok(Tonemap {
+            context: display.get_context().clone(),
+            program: fullscreen_program(display, "post/tonemap.glsl", &Defines::new())?,
+            luminance_program: fullscreen_program(display, "post/luminance.glsl", &Defines::new())?,
+            adapt_program: fullscreen_program(display, "post/adapt.glsl", &Defines::new())?,
+            operator: ToneMapOperator::Aces,
+            luminance: None,
+            adapted: [adapted_target(display)?, adapted_target(display)?],
+            current: 0,
+            last_frame: None,
+        })
+    }
+
+    // Updates the adapted luminance from the light buffer
+    fn adapt(&mut self, inputs: &PostInputs) -> Result<(), Box<Error>> {
+        let dimensions = inputs.source.dimensions();
+        let resized = match &self.luminance {
+            Some(luminance) => luminance.dimensions() != dimensions,
+            None => true,
+        };
+        if resized {
+            self.luminance = Some(Texture2d::empty_with_format(
+                &self.context,
+                UncompressedFloatFormat::F32,
+                EmptyMipmaps,
+                dimensions.0,
+                dimensions.1,
+            )?);
+        }
+        let luminance = self.luminance.as_ref().expect("luminance was just created");
+
+        let mut framebuffer = SimpleFrameBuffer::new(&self.context, luminance)?;
+        draw_fullscreen(&mut framebuffer, &self.luminance_program, inputs)?;
+        // Safe since the whole of the main level has just been drawn
+        unsafe {
+            luminance.generate_mipmaps();
+        }
+
+        let now = Instant::now();
+        let blend = match self.last_frame {
+            Some(last) => {
+                let elapsed = now - last;
+                let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+                1.0 - (-seconds * inputs.camera.exposure.adaptation_rate).exp()
+            }
+            None => 1.0,
+        };
+        self.last_frame = Some(now);
+
+        let next = 1 - self.current;
+        let uniforms = uniform! {
+            luminance_tex: luminance
+                .sampled()
+                .minify_filter(MinifySamplerFilter::NearestMipmapNearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest),
+            luminance_lod: (luminance.get_mipmap_levels() - 1) as f32,
+            previous_tex: &self.adapted[self.current],
+            blend: blend,
+        };
+        let mut framebuffer = SimpleFrameBuffer::new(&self.context, &self.adapted[next])?;
+        draw_fullscreen(&mut framebuffer, &self.adapt_program, &uniforms)?;
+        self.current = next;
+        Ok(())
+    }
+}
+
+// A 1x1 adapted luminance target, cleared to 1. The first frame of auto exposure still reads it,
+// only with a weight of 0, and undefined contents could be NaN
+fn adapted_target<F: Facade>(facade: &F) -> Result<Texture2d, Box<Error>> {
+    let target = Texture2d::empty_with_format(facade, UncompressedFloatFormat::F32, NoMipmap, 1, 1)?;
+    target.as_surface().clear_color(1.0, 0.0, 0.0, 1.0);
+    Ok(target)
+}
+
+impl PostPass for Tonemap {
+    fn name(&self) -> &str {
+        "tonemap"
+    }
+
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>> {
+        let exposure = inputs.camera.exposure;
+        if exposure.auto {
+            self.adapt(inputs)?;
+        } else {
+            // Auto exposure starts over when it is turned back on
+            self.last_frame = None;
+        }
+
+        let white_point = match self.operator {
+            ToneMapOperator::Uncharted2 { white_point } => white_point,
+            _ => 1.0,
+        };
+        let uniforms = uniform! {
+            adapted_tex: &self.adapted[self.current],
+            exposure: exposure.manual,
+            auto_exposure: exposure.auto,
+            key: exposure.key,
+            compensation: exposure.compensation,
+            min_exposure: exposure.min,
+            max_exposure: exposure.max,
+            tonemap_operator: self.operator.index(),
+            white_point: white_point,
+        };
+        draw_fullscreen(target, &self.program, &UniformsChain(inputs, &uniforms))
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// Fast approximate anti-aliasing. Should come after tone mapping
+pub struct Fxaa {
+    program: Program,
+    // Longest edge search in pixels
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Fxaa {
+    pub fn new(display: &Display) -> Result<Fxaa, Box<Error>> {
+        Ok(Fxaa {
+            program: fullscreen_program(display, "post/fxaa.glsl", &Defines::new())?,
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        })
+    }
+}
+
+impl PostPass for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>> {
+        let uniforms = uniform! {
+            span_max: self.span_max,
+            reduce_mul: self.reduce_mul,
+            reduce_min: self.reduce_min,
+        };
+        draw_fullscreen(target, &self.program, &UniformsChain(inputs, &uniforms))
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// Darkens the corners of the image
+pub struct Vignette {
+    program: Program,
+    // 0 disables the vignette, 1 makes the corners black
+    pub strength: f32,
+    // Distance from the centre, in 0..1 screen coordinates, where the darkening ends
+    pub radius: f32,
+    pub softness: f32,
+}
+
+impl Vignette {
+    pub fn new(display: &Display) -> Result<Vignette, Box<Error>> {
+        Ok(Vignette {
+            program: fullscreen_program(display, "post/vignette.glsl", &Defines::new())?,
+            strength: 0.5,
+            radius: 0.75,
+            softness: 0.45,
+        })
+    }
+}
+
+impl PostPass for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>> {
+        let uniforms = uniform! {
+            strength: self.strength,
+            radius: self.radius,
+            softness: self.softness,
+        };
+        draw_fullscreen(target, &self.program, &UniformsChain(inputs, &uniforms))
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// Blurs the light above a threshold and adds it back, so bright areas glow. The light is
+// downsampled through a chain of half size levels and then upsampled back up it, each level
+// adding in the blur of the one below. Reads HDR colour, so it goes before `Tonemap` in a chain,
+// e.g. `chain.insert(0, Bloom::new(display)?)`, or can be drawn on its own with `draw`
+pub struct Bloom {
+    context: Rc<Context>,
+    threshold_program: Program,
+    downsample_program: Program,
+    upsample_program: Program,
+    program: Program,
+    // Fraction of the blurred light added back
+    pub intensity: f32,
+    // Brightness, as the largest channel, above which light blooms
+    pub threshold: f32,
+    // Width of the soft transition around the threshold, 0 for a hard cut
+    pub knee: f32,
+    // Spread of the upsampling filter in texels. Larger values give a wider, less smooth glow
+    pub radius: f32,
+    // Most levels in the chain. More levels reach further across the screen
+    pub levels: u32,
+    // The downsampled levels and their upsampled sums, largest first
+    down: Vec<Texture2d>,
+    up: Vec<Texture2d>,
+}
+
+impl Bloom {
+    pub fn new(display: &Display) -> Result<Bloom, Box<Error>> {
+        Ok(Bloom {
+            context: display.get_context().clone(),
+            threshold_program: fullscreen_program(
+                display,
+                "post/bloom_downsample.glsl",
+                &flags(&["BLOOM_THRESHOLD"]),
+            )?,
+            downsample_program: fullscreen_program(display, "post/bloom_downsample.glsl", &Defines::new())?,
+            upsample_program: fullscreen_program(display, "post/bloom_upsample.glsl", &Defines::new())?,
+            program: fullscreen_program(display, "post/bloom.glsl", &Defines::new())?,
+            intensity: 0.1,
+            threshold: 1.0,
+            knee: 0.5,
+            radius: 1.0,
+            levels: 6,
+            down: Vec::new(),
+            up: Vec::new(),
+        })
+    }
+
+    // Runs the chain over `source`. The result is left in `texture`
+    pub fn render(&mut self, source: &Texture2d) -> Result<(), Box<Error>> {
+        self.resize_levels(source.dimensions())?;
+
+        let uniforms = uniform! {
+            source_tex: bilinear(source),
+            threshold: self.threshold,
+            knee: self.knee,
+        };
+        let mut framebuffer = SimpleFrameBuffer::new(&self.context, &self.down[0])?;
+        draw_fullscreen(&mut framebuffer, &self.threshold_program, &uniforms)?;
+
+        for i in 1..self.down.len() {
+            let uniforms = uniform! {
+                source_tex: bilinear(&self.down[i - 1]),
+            };
+            let mut framebuffer = SimpleFrameBuffer::new(&self.context, &self.down[i])?;
+            draw_fullscreen(&mut framebuffer, &self.downsample_program, &uniforms)?;
+        }
+
+        // The smallest level has nothing below it, so it is used as is
+        for i in (0..self.up.len()).rev() {
+            let coarser = if i + 1 < self.up.len() { &self.up[i + 1] } else { &self.down[i + 1] };
+            let uniforms = uniform! {
+                current_tex: bilinear(&self.down[i]),
+                coarser_tex: bilinear(coarser),
+                radius: self.radius,
+            };
+            let mut framebuffer = SimpleFrameBuffer::new(&self.context, &self.up[i])?;
+            draw_fullscreen(&mut framebuffer, &self.upsample_program, &uniforms)?;
+        }
+        Ok(())
+    }
+
+    // The bloom of the last `render` at half the source's size, before `intensity` is applied
+    pub fn texture(&self) -> Option<&Texture2d> {
+        self.up.first().or_else(|| self.down.first())
+    }
+
+    // Renders the bloom of `source` and draws `source` with it added into `surface`
+    pub fn draw<S: Surface>(&mut self, surface: &mut S, source: &Texture2d) -> Result<(), Box<Error>> {
+        self.render(source)?;
+        let bloom = self.texture().expect("render creates the levels");
+        let uniforms = uniform! {
+            source_tex: source,
+            bloom_tex: bilinear(bloom),
+            radius: self.radius,
+            bloom_scale: self.intensity / self.down.len() as f32,
+        };
+        draw_fullscreen(surface, &self.program, &uniforms)
+    }
+
+    fn resize_levels(&mut self, dimensions: (u32, u32)) -> Result<(), Box<Error>> {
+        let sizes = bloom_levels(dimensions, self.levels);
+        let current: Vec<(u32, u32)> = self.down.iter().map(|level| level.dimensions()).collect();
+        if current == sizes {
+            return Ok(());
+        }
+
+        let context = &self.context;
+        let level = |&(width, height): &(u32, u32)| {
+            Texture2d::empty_with_format(
+                context,
+                UncompressedFloatFormat::F16F16F16F16,
+                NoMipmap,
+                width,
+                height,
+            )
+        };
+        self.down = sizes.iter().map(&level).collect::<Result<_, _>>()?;
+        self.up = sizes[..sizes.len() - 1].iter().map(&level).collect::<Result<_, _>>()?;
+        Ok(())
+    }
+}
+
+// The sizes of the downsampled levels for a source of `dimensions`, halving until either side
+// would drop below 2 pixels or there are `levels` of them. There is always at least one level,
+// at least 1 pixel wide
+fn bloom_levels(dimensions: (u32, u32), levels: u32) -> Vec<(u32, u32)> {
+    let (mut width, mut height) = (dimensions.0 / 2, dimensions.1 / 2);
+    let mut sizes = vec![(width.max(1), height.max(1))];
+    while sizes.len() < levels as usize && width >= 4 && height >= 4 {
+        width /= 2;
+        height /= 2;
+        sizes.push((width, height));
+    }
+    sizes
+}
+
+// Clamped so the filters don't wrap around the edges of the screen
+fn bilinear(texture: &Texture2d) -> Sampler<Texture2d> {
+    texture
+        .sampled()
+        .wrap_function(SamplerWrapFunction::Clamp)
+        .minify_filter(MinifySamplerFilter::Linear)
+        .magnify_filter(MagnifySamplerFilter::Linear)
+}
+
+impl PostPass for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>> {
+        self.draw(target, inputs.source)
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// Runs an ordered list of passes over the light buffer, each one reading the output of the
+// previous one from a pair of ping-pong targets, then presents the result with gamma correction
+pub struct PostChain {
+    context: Rc<Context>,
+    passes: Vec<Box<PostPass>>,
+    targets: Option<[Texture2d; 2]>,
+    gamma_program: Program,
+    pub gamma: f32,
+}
+
+impl PostChain {
+    // An empty chain, which presents the light buffer as is
+    pub fn new(display: &Display) -> Result<PostChain, Box<Error>> {
+        Ok(PostChain {
+            context: display.get_context().clone(),
+            passes: Vec::new(),
+            targets: None,
+            gamma_program: fullscreen_program(display, "post/gamma.glsl", &Defines::new())?,
+            gamma: 2.2,
+        })
+    }
+
+    // Tone mapping, FXAA and a vignette
+    pub fn with_defaults(display: &Display) -> Result<PostChain, Box<Error>> {
+        let mut chain = PostChain::new(display)?;
+        chain.push(Tonemap::new(display)?);
+        chain.push(Fxaa::new(display)?);
+        chain.push(Vignette::new(display)?);
+        Ok(chain)
+    }
+
+    pub fn push<P: PostPass + 'static>(&mut self, pass: P) {
+        self.passes.push(Box::new(pass));
+    }
+
+    pub fn insert<P: PostPass + 'static>(&mut self, index: usize, pass: P) {
+        self.passes.insert(index, Box::new(pass));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<PostPass>> {
+        let index = self.passes.iter().position(|pass| pass.name() == name)?;
+        Some(self.passes.remove(index))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    // The first pass with this name, if it is a `P`
+    pub fn get_mut<P: PostPass + 'static>(&mut self, name: &str) -> Option<&mut P> {
+        self.passes
+            .iter_mut()
+            .find(|pass| pass.name() == name)
+            .and_then(|pass| pass.as_any_mut().downcast_mut::<P>())
+    }
+
+    // `occlusion` is the one `LightingPass::draw_ambient` was given
+    pub fn render<S: Surface>(
+        &mut self,
+        surface: &mut S,
+        gbuffer: &GBuffer,
+        occlusion: Option<&Ssao>,
+        camera: &PCamera,
+    ) -> Result<(), Box<Error>> {
+        self.resize_targets(gbuffer.light.dimensions())?;
+        let targets = self.targets.as_ref().expect("targets were just created");
+
+        let mut source = &gbuffer.light;
+        for (i, pass) in self.passes.iter_mut().enumerate() {
+            let target = &targets[i % 2];
+            let mut framebuffer = SimpleFrameBuffer::new(&self.context, target)?;
+            let inputs = PostInputs {
+                source,
+                gbuffer,
+                occlusion,
+                camera,
+            };
+            pass.apply(&mut framebuffer, &inputs)?;
+            source = target;
+        }
+
+        let uniforms = uniform! {
+            source_tex: source,
+            gamma: self.gamma,
+        };
+        draw_fullscreen(surface, &self.gamma_program, &uniforms)
+    }
+
+    fn resize_targets(&mut self, dimensions: (u32, u32)) -> Result<(), Box<Error>> {
+        if let Some(targets) = &self.targets {
+            if targets[0].dimensions() == dimensions {
+                return Ok(());
+            }
+        }
+
+        let context = &self.context;
+        let target = || {
+            Texture2d::empty_with_format(
+                context,
+                UncompressedFloatFormat::F16F16F16F16,
+                NoMipmap,
+                dimensions.0,
+                dimensions.1,
+            )
+        };
+        self.targets = Some([target()?, target()?]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_levels_halve_the_source() {
+        assert_eq!(
+            bloom_levels((1920, 1080), 6),
+            vec![(960, 540), (480, 270), (240, 135), (120, 67), (60, 33), (30, 16)]
+        );
+        // Odd sizes round down
+        assert_eq!(bloom_levels((101, 51), 2), vec![(50, 25), (25, 12)]);
+    }
+
+    #[test]
+    fn bloom_levels_stop_at_2_pixels() {
+        assert_eq!(bloom_levels((64, 16), 10), vec![(32, 8), (16, 4), (8, 2)]);
+        assert_eq!(bloom_levels((16, 64), 10), vec![(8, 32), (4, 16), (2, 8)]);
+    }
+
+    #[test]
+    fn bloom_levels_are_capped() {
+        assert_eq!(bloom_levels((1024, 1024), 1), vec![(512, 512)]);
+        assert_eq!(bloom_levels((1024, 1024), 3).len(), 3);
+        // A single level even if none are asked for
+        assert_eq!(bloom_levels((1024, 1024), 0), vec![(512, 512)]);
+    }
+
+    #[test]
+    fn tiny_sources_get_one_level() {
+        assert_eq!(bloom_levels((3, 3), 6), vec![(1, 1)]);
+        assert_eq!(bloom_levels((1, 1), 6), vec![(1, 1)]);
+        assert_eq!(bloom_levels((0, 7), 6), vec![(1, 3)]);
+    }
+}
@@ -0,0 +1,455 @@
+// A small GLSL preprocessor. It resolves `#include "file.glsl"` relative to the shader
+// directory, evaluates `#ifdef`/`#ifndef`/`#else`/`#endif` against the defines of a permutation
+// and injects those defines after `#version`. Every included file gets a `#line` directive so
+// the driver's error log can be mapped back to the original file with `map_log`. The shader
+// test in tests/shaders.rs runs every permutation it finds through glslang
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+// Defines for one permutation of a shader. Kept sorted so it can be used as a cache key
+pub type Defines = BTreeMap<String, String>;
+
+// Defines with no value, for on/off switches
+pub fn flags(names: &[&str]) -> Defines {
+    names
+        .iter()
+        .map(|name| (name.to_string(), String::new()))
+        .collect()
+}
+
+pub enum ShaderFiles {
+    // Files are read from this directory whenever they are preprocessed
+    Directory(PathBuf),
+    // (name, source) pairs compiled into the binary with `include_str!`
+    Embedded(&'static [(&'static str, &'static str)]),
+}
+
+impl ShaderFiles {
+    pub fn read(&self, name: &str) -> Result<String, String> {
+        match self {
+            ShaderFiles::Directory(dir) => {
+                fs::read_to_string(dir.join(name)).map_err(|err| err.to_string())
+            }
+            ShaderFiles::Embedded(files) => files
+                .iter()
+                .find(|(file, _)| *file == name)
+                .map(|(_, source)| source.to_string())
+                .ok_or_else(|| "no embedded shader with that name".to_string()),
+        }
+    }
+
+    // The file on disk that `name` refers to, if the shaders aren't embedded
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        match self {
+            ShaderFiles::Directory(dir) => Some(dir.join(name)),
+            ShaderFiles::Embedded(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PreprocessError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+impl Error for PreprocessError {
+    fn description(&self) -> &str {
+        "failed to preprocess shader"
+    }
+}
+
+// Preprocesses the stages of one program. The file table is shared between every call so
+// each file has a unique source number across all stages of the program
+pub struct Preprocessor<'a> {
+    files: &'a ShaderFiles,
+    sources: Vec<String>,
+    conditions: BTreeSet<String>,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(files: &'a ShaderFiles) -> Preprocessor<'a> {
+        Preprocessor {
+            files,
+            sources: Vec::new(),
+            conditions: BTreeSet::new(),
+        }
+    }
+
+    // Every file that has been read, indexed by its `#line` source number
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    // Every name tested by an `#ifdef` or `#ifndef`, i.e. the switches of the permutation.
+    // Files only included in a skipped branch are scanned too, so their switches are listed
+    pub fn conditions(&self) -> &BTreeSet<String> {
+        &self.conditions
+    }
+
+    pub fn process(&mut self, name: &str, defines: &Defines) -> Result<String, PreprocessError> {
+        let mut state = State {
+            output: String::new(),
+            defined: defines.keys().cloned().collect(),
+            stack: Vec::new(),
+            included: BTreeSet::new(),
+            found_version: false,
+        };
+
+        self.process_file(name, defines, &mut state)?;
+        if !state.found_version {
+            return Err(PreprocessError {
+                file: name.to_string(),
+                line: 0,
+                message: "shader has no #version directive".to_string(),
+            });
+        }
+        Ok(state.output)
+    }
+
+    fn process_file(&mut self, name: &str, defines: &Defines, state: &mut State) -> Result<(), PreprocessError> {
+        let error = |line: usize, message: String| PreprocessError {
+            file: name.to_string(),
+            line,
+            message,
+        };
+
+        if state.stack.contains(&name.to_string()) {
+            return Err(error(0, "recursive #include".to_string()));
+        }
+        // Files are only included once per stage, like #pragma once
+        if !state.included.insert(name.to_string()) {
+            return Ok(());
+        }
+
+        let source = self.files.read(name).map_err(|err| error(0, err))?;
+        let index = self.sources.len();
+        self.sources.push(name.to_string());
+        state.stack.push(name.to_string());
+
+        let is_root = state.stack.len() == 1;
+        if !is_root {
+            state.output.push_str(&format!("#line 1 {}\n", index));
+        }
+
+        // (active, a branch has already been taken)
+        let mut conditionals: Vec<(bool, bool)> = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let active = conditionals.iter().all(|&(active, _)| active);
+            let directive = parse_directive(line);
+
+            match directive {
+                Some((kind, arg)) if kind == "ifdef" || kind == "ifndef" => {
+                    let arg = arg.trim();
+                    self.conditions.insert(arg.to_string());
+                    let defined = state.defined.contains(arg);
+                    let taken = if kind == "ifndef" { !defined } else { defined };
+                    conditionals.push((taken, taken));
+                    state.output.push('\n');
+                }
+                Some(("else", _)) => {
+                    match conditionals.last_mut() {
+                        Some(top) => *top = (!top.1, true),
+                        None => return Err(error(line_number, "#else without #ifdef".to_string())),
+                    }
+                    state.output.push('\n');
+                }
+                Some(("endif", _)) => {
+                    if conditionals.pop().is_none() {
+                        return Err(error(line_number, "#endif without #ifdef".to_string()));
+                    }
+                    state.output.push('\n');
+                }
+                Some(("if", _)) | Some(("elif", _)) => {
+                    return Err(error(line_number, "only #ifdef and #ifndef conditionals are supported".to_string()));
+                }
+                Some(("include", arg)) if !active => {
+                    let include = arg.trim().trim_matches('"');
+                    self.collect_conditions(include, &mut BTreeSet::new())?;
+                    state.output.push('\n');
+                }
+                _ if !active => state.output.push('\n'),
+                Some(("version", _)) => {
+                    if !is_root {
+                        return Err(error(line_number, "#version in an included file".to_string()));
+                    }
+                    state.found_version = true;
+                    state.output.push_str(line);
+                    state.output.push('\n');
+                    for (define, value) in defines {
+                        state.output.push_str(&format!("#define {} {}\n", define, value));
+                    }
+                    state.output.push_str(&format!("#line {} {}\n", line_number + 1, index));
+                }
+                Some(("include", arg)) => {
+                    let include = arg.trim().trim_matches('"');
+                    if include.is_empty() || !arg.trim().starts_with('"') {
+                        return Err(error(line_number, format!("invalid #include {}", arg.trim())));
+                    }
+                    self.process_file(include, defines, state)?;
+                    state.output.push_str(&format!("#line {} {}\n", line_number + 1, index));
+                }
+                Some(("define", arg)) => {
+                    if let Some(define) = arg.split_whitespace().next() {
+                        state.defined.insert(define.to_string());
+                    }
+                    state.output.push_str(line);
+                    state.output.push('\n');
+                }
+                Some(("undef", arg)) => {
+                    state.defined.remove(arg.trim());
+                    state.output.push_str(line);
+                    state.output.push('\n');
+                }
+                _ => {
+                    state.output.push_str(line);
+                    state.output.push('\n');
+                }
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(error(0, "unterminated #ifdef".to_string()));
+        }
+
+        state.stack.pop();
+        Ok(())
+    }
+
+    // Adds the switches of a file and everything it includes, in every branch
+    fn collect_conditions(&mut self, name: &str, visited: &mut BTreeSet<String>) -> Result<(), PreprocessError> {
+        if !visited.insert(name.to_string()) {
+            return Ok(());
+        }
+        let source = self.files.read(name).map_err(|message| PreprocessError {
+            file: name.to_string(),
+            line: 0,
+            message,
+        })?;
+
+        for line in source.lines() {
+            match parse_directive(line) {
+                Some((kind, arg)) if kind == "ifdef" || kind == "ifndef" => {
+                    self.conditions.insert(arg.trim().to_string());
+                }
+                Some(("include", arg)) => {
+                    self.collect_conditions(arg.trim().trim_matches('"'), visited)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces source numbers in a driver error log with file names. Handles the common
+    // "0(12)" (nvidia) and "0:12" (mesa, amd) styles
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| self.map_log_line(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn map_log_line(&self, line: &str) -> String {
+        let bytes = line.as_bytes();
+        let mut start = 0;
+
+        while start < bytes.len() {
+            if !bytes[start].is_ascii_digit() || (start > 0 && bytes[start - 1].is_ascii_alphanumeric()) {
+                start += 1;
+                continue;
+            }
+
+            let mut end = start;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+            let followed_by_line = end + 1 < bytes.len()
+                && (bytes[end] == b'(' || bytes[end] == b':')
+                && bytes[end + 1].is_ascii_digit();
+
+            if followed_by_line {
+                if let Some(name) = line[start..end].parse::<usize>().ok().and_then(|i| self.sources.get(i)) {
+                    return format!("{}{}{}", &line[..start], name, &line[end..]);
+                }
+            }
+            start = end;
+        }
+
+        line.to_string()
+    }
+}
+
+struct State {
+    output: String,
+    defined: BTreeSet<String>,
+    stack: Vec<String>,
+    included: BTreeSet<String>,
+    found_version: bool,
+}
+
+// Splits "#  include "x.glsl"" into ("include", " \"x.glsl\"")
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if !line.starts_with('#') {
+        return None;
+    }
+    let rest = line[1..].trim_start();
+    let end = rest
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or_else(|| rest.len());
+    Some((&rest[..end], &rest[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILES: &[(&str, &str)] = &[
+        (
+            "main.glsl",
+            "#version 330\n\
+             #include \"common.glsl\"\n\
+             #include \"common.glsl\"\n\
+             #ifdef SHADOWS\n\
+             #include \"shadows.glsl\"\n\
+             #endif\n\
+             void main() {}\n",
+        ),
+        ("common.glsl", "float common_value;\n"),
+        (
+            "shadows.glsl",
+            "#ifdef PCF\n\
+             float pcf;\n\
+             #else\n\
+             float hard;\n\
+             #endif\n",
+        ),
+        (
+            "nested.glsl",
+            "#version 330\n\
+             #ifdef A\n\
+             #ifndef B\n\
+             float a_only;\n\
+             #else\n\
+             float a_and_b;\n\
+             #endif\n\
+             #else\n\
+             float neither;\n\
+             #endif\n",
+        ),
+    ];
+
+    fn process(name: &str, defines: &[&str]) -> String {
+        let files = ShaderFiles::Embedded(FILES);
+        Preprocessor::new(&files).process(name, &flags(defines)).unwrap()
+    }
+
+    fn code(output: &str) -> Vec<&str> {
+        output
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect()
+    }
+
+    #[test]
+    fn includes_are_resolved_once() {
+        let output = process("main.glsl", &[]);
+        assert_eq!(code(&output), vec!["float common_value;", "void main() {}"]);
+    }
+
+    #[test]
+    fn defines_follow_version() {
+        let output = process("main.glsl", &["SHADOWS", "PCF"]);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..4], ["#version 330", "#define PCF ", "#define SHADOWS ", "#line 2 0"]);
+        assert_eq!(code(&output), vec!["float common_value;", "float pcf;", "void main() {}"]);
+    }
+
+    #[test]
+    fn conditionals_nest() {
+        assert_eq!(code(&process("nested.glsl", &[])), vec!["float neither;"]);
+        assert_eq!(code(&process("nested.glsl", &["A"])), vec!["float a_only;"]);
+        assert_eq!(code(&process("nested.glsl", &["A", "B"])), vec!["float a_and_b;"]);
+        assert_eq!(code(&process("nested.glsl", &["B"])), vec!["float neither;"]);
+    }
+
+    #[test]
+    fn line_directives_name_the_source() {
+        let files = ShaderFiles::Embedded(FILES);
+        let mut preprocessor = Preprocessor::new(&files);
+        let output = preprocessor.process("main.glsl", &flags(&["SHADOWS"])).unwrap();
+        assert_eq!(preprocessor.sources(), ["main.glsl", "common.glsl", "shadows.glsl"]);
+
+        // Each line after a `#line N i` directive is line N of source i
+        let mut location = (0, 0);
+        let mut found = Vec::new();
+        for line in output.lines() {
+            if line.starts_with("#line") {
+                let parts: Vec<usize> = line[5..].split_whitespace().map(|n| n.parse().unwrap()).collect();
+                location = (parts[0], parts[1]);
+                continue;
+            }
+            if !line.is_empty() && !line.starts_with('#') {
+                found.push((line, location));
+            }
+            location.0 += 1;
+        }
+        assert_eq!(
+            found,
+            vec![
+                ("float common_value;", (1, 1)),
+                ("float hard;", (4, 2)),
+                ("void main() {}", (7, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditions_include_skipped_branches() {
+        let files = ShaderFiles::Embedded(FILES);
+        let mut preprocessor = Preprocessor::new(&files);
+        preprocessor.process("main.glsl", &Defines::new()).unwrap();
+        let conditions: Vec<&str> = preprocessor.conditions().iter().map(|c| c.as_str()).collect();
+        assert_eq!(conditions, ["PCF", "SHADOWS"]);
+    }
+
+    #[test]
+    fn log_source_numbers_are_replaced() {
+        let files = ShaderFiles::Embedded(FILES);
+        let mut preprocessor = Preprocessor::new(&files);
+        preprocessor.process("main.glsl", &Defines::new()).unwrap();
+
+        let log = "0(7) : error C1008: undefined variable\nERROR: 1:1: 'x' : syntax error\nERROR: 2 compilation errors";
+        assert_eq!(
+            preprocessor.map_log(log),
+            "main.glsl(7) : error C1008: undefined variable\n\
+             ERROR: common.glsl:1: 'x' : syntax error\n\
+             ERROR: 2 compilation errors"
+        );
+    }
+
+    #[test]
+    fn missing_version_is_an_error() {
+        let files = ShaderFiles::Embedded(FILES);
+        let err = Preprocessor::new(&files).process("common.glsl", &Defines::new()).unwrap_err();
+        assert_eq!(err.file, "common.glsl");
+    }
+}
@@ -0,0 +1,430 @@
+use glium::backend::glutin::Display;
+use glium::Program;
+use material::MaterialModel;
+use std::collections::HashMap;
+use std::error::Error;
+use preprocess::{flags, Defines, Preprocessor, ShaderFiles};
+use std::path::{Path, PathBuf};
+use watch::FileWatcher;
+
+// Every shipped shader, so the built-in programs don't depend on the source tree at runtime
+pub static EMBEDDED_SHADERS: &[(&str, &str)] = &[
+    ("common.glsl", include_str!("shaders/common.glsl")),
+    ("prepass/vertex.glsl", include_str!("shaders/prepass/vertex.glsl")),
+    ("prepass/fragment.glsl", include_str!("shaders/prepass/fragment.glsl")),
+    ("lighting/vertex.glsl", include_str!("shaders/lighting/vertex.glsl")),
+    ("lighting/brdf.glsl", include_str!("shaders/lighting/brdf.glsl")),
+    ("lighting/shade.glsl", include_str!("shaders/lighting/shade.glsl")),
+    ("lighting/surface.glsl", include_str!("shaders/lighting/surface.glsl")),
+    ("lighting/fragment.glsl", include_str!("shaders/lighting/fragment.glsl")),
+    ("lighting/clustered.glsl", include_str!("shaders/lighting/clustered.glsl")),
+    ("lighting/ambient.glsl", include_str!("shaders/lighting/ambient.glsl")),
+    ("lighting/shadow.glsl", include_str!("shaders/lighting/shadow.glsl")),
+    ("lighting/shadow_cube.glsl", include_str!("shaders/lighting/shadow_cube.glsl")),
+    ("shadow/vertex.glsl", include_str!("shaders/shadow/vertex.glsl")),
+    ("shadow/depth.glsl", include_str!("shaders/shadow/depth.glsl")),
+    ("shadow/distance.glsl", include_str!("shaders/shadow/distance.glsl")),
+    ("lighting/stencil.glsl", include_str!("shaders/lighting/stencil.glsl")),
+    ("lighting/copy_depth.glsl", include_str!("shaders/lighting/copy_depth.glsl")),
+    ("ibl/common.glsl", include_str!("shaders/ibl/common.glsl")),
+    ("ibl/equirect_to_cube.glsl", include_str!("shaders/ibl/equirect_to_cube.glsl")),
+    ("ibl/irradiance.glsl", include_str!("shaders/ibl/irradiance.glsl")),
+    ("ibl/prefilter.glsl", include_str!("shaders/ibl/prefilter.glsl")),
+    ("ibl/brdf_lut.glsl", include_str!("shaders/ibl/brdf_lut.glsl")),
+    ("sky/fragment.glsl", include_str!("shaders/sky/fragment.glsl")),
+    ("ssao/occlusion.glsl", include_str!("shaders/ssao/occlusion.glsl")),
+    ("ssao/blur.glsl", include_str!("shaders/ssao/blur.glsl")),
+    ("ssr/fragment.glsl", include_str!("shaders/ssr/fragment.glsl")),
+    ("fullscreen/vertex.glsl", include_str!("shaders/fullscreen/vertex.glsl")),
+    ("readback/linear_depth.glsl", include_str!("shaders/readback/linear_depth.glsl")),
+    ("debug/fragment.glsl", include_str!("shaders/debug/fragment.glsl")),
+    ("post/tonemap.glsl", include_str!("shaders/post/tonemap.glsl")),
+    ("post/luminance.glsl", include_str!("shaders/post/luminance.glsl")),
+    ("post/adapt.glsl", include_str!("shaders/post/adapt.glsl")),
+    ("post/fxaa.glsl", include_str!("shaders/post/fxaa.glsl")),
+    ("post/vignette.glsl", include_str!("shaders/post/vignette.glsl")),
+    ("post/bloom_filters.glsl", include_str!("shaders/post/bloom_filters.glsl")),
+    ("post/bloom_downsample.glsl", include_str!("shaders/post/bloom_downsample.glsl")),
+    ("post/bloom_upsample.glsl", include_str!("shaders/post/bloom_upsample.glsl")),
+    ("post/bloom.glsl", include_str!("shaders/post/bloom.glsl")),
+    ("post/gamma.glsl", include_str!("shaders/post/gamma.glsl")),
+];
+
+// Defines for the prepass permutations. `prepass_program` enables both
+pub fn prepass_defines(parallax_mapping: bool, normal_mapping: bool) -> Defines {
+    let mut names = Vec::new();
+    if parallax_mapping {
+        names.push("PARALLAX_MAPPING");
+    }
+    if normal_mapping {
+        names.push("NORMAL_MAPPING");
+    }
+    flags(&names)
+}
+
+// Program for `PrePass::draw_object` using the shipped prepass shaders
+pub fn prepass_program(display: &Display) -> Result<Program, Box<Error>> {
+    let files = ShaderFiles::Embedded(EMBEDDED_SHADERS);
+    let defines = prepass_defines(true, true);
+    let (program, _) = compile_program(
+        display,
+        &files,
+        "prepass/vertex.glsl",
+        "prepass/fragment.glsl",
+        None,
+        &defines,
+    )?;
+    Ok(program)
+}
+
+// Program for `LightingPass::draw_light` using the shipped lighting shaders, with Blinn-Phong
+// and no shadows
+pub fn lighting_program(display: &Display) -> Result<Program, Box<Error>> {
+    lighting_program_with(display, &Defines::new())
+}
+
+// Defines for the lighting permutations. `shadows` is `Some(ShadowMap::is_cube())` for lights
+// drawn with `LightingPass::draw_shadowed_light`
+pub fn lighting_defines(model: MaterialModel, shadows: Option<bool>) -> Defines {
+    let mut defines = model.defines();
+    match shadows {
+        Some(true) => defines.extend(flags(&["CUBE_SHADOWS"])),
+        Some(false) => defines.extend(flags(&["SHADOWS"])),
+        None => {}
+    }
+    defines
+}
+
+pub fn lighting_program_with(display: &Display, defines: &Defines) -> Result<Program, Box<Error>> {
+    let files = ShaderFiles::Embedded(EMBEDDED_SHADERS);
+    let (program, _) = compile_program(
+        display,
+        &files,
+        "lighting/vertex.glsl",
+        "lighting/fragment.glsl",
+        None,
+        defines,
+    )?;
+    Ok(program)
+}
+
+// The Blinn-Phong lighting program with shadows from a `ShadowMap`, `cube` being
+// `ShadowMap::is_cube`
+pub fn shadowed_lighting_program(display: &Display, cube: bool) -> Result<Program, Box<Error>> {
+    lighting_program_with(display, &lighting_defines(MaterialModel::BlinnPhong, Some(cube)))
+}
+
+// Image based ambient lighting from an `Environment`, see `LightingPass::draw_ambient`.
+// `occlusion` compiles in the `Ssao` lookup
+pub fn ambient_program(
+    display: &Display,
+    model: MaterialModel,
+    occlusion: bool,
+) -> Result<Program, Box<Error>> {
+    let mut defines = model.defines();
+    if occlusion {
+        defines.extend(flags(&["SSAO"]));
+    }
+    fullscreen_program(display, "lighting/ambient.glsl", &defines)
+}
+
+// Renders objects into a shadow map, writing the distance to the light for cube maps
+pub fn shadow_program(display: &Display, cube: bool) -> Result<Program, Box<Error>> {
+    let files = ShaderFiles::Embedded(EMBEDDED_SHADERS);
+    let fragment = if cube { "shadow/distance.glsl" } else { "shadow/depth.glsl" };
+    let (program, _) = compile_program(
+        display,
+        &files,
+        "shadow/vertex.glsl",
+        fragment,
+        None,
+        &Defines::new(),
+    )?;
+    Ok(program)
+}
+
+// Draws light volumes into the stencil buffer only, see `LightVolumes`
+pub fn light_stencil_program(display: &Display) -> Result<Program, Box<Error>> {
+    let files = ShaderFiles::Embedded(EMBEDDED_SHADERS);
+    let (program, _) = compile_program(
+        display,
+        &files,
+        "lighting/vertex.glsl",
+        "lighting/stencil.glsl",
+        None,
+        &Defines::new(),
+    )?;
+    Ok(program)
+}
+
+// A program drawing an embedded fragment shader over a full-screen triangle. The fragment
+// shader gets the 0..1 screen coordinate as `f_tex`
+pub fn fullscreen_program(
+    display: &Display,
+    fragment: &str,
+    defines: &Defines,
+) -> Result<Program, Box<Error>> {
+    let files = ShaderFiles::Embedded(EMBEDDED_SHADERS);
+    let (program, _) = compile_program(
+        display,
+        &files,
+        "fullscreen/vertex.glsl",
+        fragment,
+        None,
+        defines,
+    )?;
+    Ok(program)
+}
+
+// Shades every light of a pixel's cluster in one pass, see `ClusteredLights`
+pub fn clustered_lighting_program(
+    display: &Display,
+    model: MaterialModel,
+) -> Result<Program, Box<Error>> {
+    fullscreen_program(display, "lighting/clustered.glsl", &model.defines())
+}
+
+// Copies `depth_tex` into the depth of the target, see `PrePass::begin_lighting`
+pub fn depth_copy_program(display: &Display) -> Result<Program, Box<Error>> {
+    fullscreen_program(display, "lighting/copy_depth.glsl", &Defines::new())
+}
+
+// Writes the linear depth of `depth_tex`, see `GBuffer::read_linear_depth`
+pub fn linear_depth_program(display: &Display) -> Result<Program, Box<Error>> {
+    fullscreen_program(display, "readback/linear_depth.glsl", &Defines::new())
+}
+
+// Draws a G-buffer channel, see `DebugPass`
+pub fn debug_program(display: &Display) -> Result<Program, Box<Error>> {
+    fullscreen_program(display, "debug/fragment.glsl", &Defines::new())
+}
+
+// Directory holding the shaders that ship with the crate
+pub fn shader_dir() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders"))
+}
+
+// Preprocesses and compiles a program, returning it along with every file it was built from.
+// Compile errors in the log refer to the original files rather than the preprocessed source
+pub fn compile_program(
+    display: &Display,
+    files: &ShaderFiles,
+    vertex: &str,
+    fragment: &str,
+    geometry: Option<&str>,
+    defines: &Defines,
+) -> Result<(Program, Vec<String>), Box<Error>> {
+    let mut preprocessor = Preprocessor::new(files);
+    let vertex = preprocessor.process(vertex, defines)?;
+    let fragment = preprocessor.process(fragment, defines)?;
+    let geometry = match geometry {
+        Some(geometry) => Some(preprocessor.process(geometry, defines)?),
+        None => None,
+    };
+
+    let program = Program::from_source(
+        display,
+        &vertex,
+        &fragment,
+        geometry.as_ref().map(|s| s.as_str()),
+    );
+    match program {
+        Ok(program) => Ok((program, preprocessor.sources().to_vec())),
+        Err(err) => Err(preprocessor.map_log(&err.to_string()).into()),
+    }
+}
+
+// Names are relative to `include_dir`, which is also where `#include`s are resolved from.
+// Absolute paths can be used for shaders outside of it
+#[derive(Clone, Debug)]
+pub struct ProgramSource {
+    pub include_dir: PathBuf,
+    pub vertex: String,
+    pub fragment: String,
+    pub geometry: Option<String>,
+    pub defines: Defines,
+}
+
+impl ProgramSource {
+    pub fn new(vertex: &str, fragment: &str) -> ProgramSource {
+        ProgramSource {
+            include_dir: shader_dir(),
+            vertex: vertex.to_string(),
+            fragment: fragment.to_string(),
+            geometry: None,
+            defines: Defines::new(),
+        }
+    }
+
+    // The vertex.glsl and fragment.glsl in a directory of `shader_dir`, e.g. "prepass"
+    pub fn shipped(name: &str) -> ProgramSource {
+        ProgramSource::new(
+            &format!("{}/vertex.glsl", name),
+            &format!("{}/fragment.glsl", name),
+        )
+    }
+
+    pub fn with_geometry(mut self, geometry: &str) -> ProgramSource {
+        self.geometry = Some(geometry.to_string());
+        self
+    }
+
+    pub fn with_defines(mut self, defines: Defines) -> ProgramSource {
+        self.defines = defines;
+        self
+    }
+
+    pub fn with_include_dir<P: AsRef<Path>>(mut self, include_dir: P) -> ProgramSource {
+        self.include_dir = include_dir.as_ref().to_path_buf();
+        self
+    }
+
+    // Compiles the program, returning it with the paths of every file it includes
+    pub fn compile(&self, display: &Display) -> Result<(Program, Vec<PathBuf>), Box<Error>> {
+        let files = ShaderFiles::Directory(self.include_dir.clone());
+        let (program, sources) = compile_program(
+            display,
+            &files,
+            &self.vertex,
+            &self.fragment,
+            self.geometry.as_ref().map(|s| s.as_str()),
+            &self.defines,
+        )?;
+        let paths = sources.iter().filter_map(|name| files.path(name)).collect();
+        Ok((program, paths))
+    }
+}
+
+// Compiles permutations of one program on demand, keeping each one keyed by its defines
+pub struct ProgramVariants<'a> {
+    display: &'a Display,
+    files: ShaderFiles,
+    vertex: String,
+    fragment: String,
+    programs: HashMap<Defines, Program>,
+}
+
+impl<'a> ProgramVariants<'a> {
+    pub fn new(display: &'a Display, files: ShaderFiles, vertex: &str, fragment: &str) -> ProgramVariants<'a> {
+        ProgramVariants {
+            display,
+            files,
+            vertex: vertex.to_string(),
+            fragment: fragment.to_string(),
+            programs: HashMap::new(),
+        }
+    }
+
+    // Variants of the shipped prepass program, see `prepass_defines`. The material model is
+    // selected by adding `MaterialModel::defines`
+    pub fn prepass(display: &'a Display) -> ProgramVariants<'a> {
+        ProgramVariants::new(
+            display,
+            ShaderFiles::Embedded(EMBEDDED_SHADERS),
+            "prepass/vertex.glsl",
+            "prepass/fragment.glsl",
+        )
+    }
+
+    // Variants of the shipped lighting program, see `lighting_defines`
+    pub fn lighting(display: &'a Display) -> ProgramVariants<'a> {
+        ProgramVariants::new(
+            display,
+            ShaderFiles::Embedded(EMBEDDED_SHADERS),
+            "lighting/vertex.glsl",
+            "lighting/fragment.glsl",
+        )
+    }
+
+    pub fn get(&mut self, defines: &Defines) -> Result<&Program, Box<Error>> {
+        if !self.programs.contains_key(defines) {
+            let (program, _) = compile_program(
+                self.display,
+                &self.files,
+                &self.vertex,
+                &self.fragment,
+                None,
+                defines,
+            )?;
+            self.programs.insert(defines.clone(), program);
+        }
+        Ok(&self.programs[defines])
+    }
+
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+}
+
+struct WatchedProgram {
+    source: ProgramSource,
+    program: Program,
+    watcher: FileWatcher,
+    error: Option<String>,
+}
+
+// Compiles programs from files and recompiles them when the files change. A program that
+// fails to recompile keeps its last good version and the GLSL error log is kept for display
+pub struct ProgramRegistry<'a> {
+    display: &'a Display,
+    programs: HashMap<String, WatchedProgram>,
+}
+
+impl<'a> ProgramRegistry<'a> {
+    pub fn new(display: &'a Display) -> ProgramRegistry<'a> {
+        ProgramRegistry {
+            display,
+            programs: HashMap::new(),
+        }
+    }
+
+    // Compiles and registers a program. Unlike a reload there is no previous version to fall
+    // back on, so a compile error is returned
+    pub fn load(&mut self, name: &str, source: ProgramSource) -> Result<&Program, Box<Error>> {
+        let (program, files) = source.compile(self.display)?;
+        let watched = WatchedProgram {
+            watcher: FileWatcher::new(files),
+            source,
+            program,
+            error: None,
+        };
+        self.programs.insert(name.to_string(), watched);
+        Ok(&self.programs[name].program)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Program> {
+        self.programs.get(name).map(|watched| &watched.program)
+    }
+
+    // The error log from the last failed reload of a program, if its latest source is broken
+    pub fn error(&self, name: &str) -> Option<&str> {
+        self.programs
+            .get(name)
+            .and_then(|watched| watched.error.as_ref().map(|e| e.as_str()))
+    }
+
+    // Recompiles every program whose files have changed since the last poll and returns the
+    // names of the ones that were replaced. Compile errors are left for `error`
+    pub fn poll(&mut self) -> Vec<String> {
+        let mut reloaded = Vec::new();
+
+        for (name, watched) in self.programs.iter_mut() {
+            if !watched.watcher.poll() {
+                continue;
+            }
+
+            match watched.source.compile(self.display) {
+                Ok((program, files)) => {
+                    // Includes may have been added or removed
+                    watched.watcher.set_files(files);
+                    watched.program = program;
+                    watched.error = None;
+                    reloaded.push(name.clone());
+                }
+                Err(err) => watched.error = Some(err.to_string()),
+            }
+        }
+
+        reloaded
+    }
+}
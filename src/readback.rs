@@ -0,0 +1,306 @@
+use glium::texture::Texture2d;
+use glium::Rect;
+use image::png::PNGEncoder;
+use image::ColorType;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    // 8 bits per channel, values are clamped to 0..1
+    Png,
+    // 16 bits per channel, values are clamped to 0..1
+    Png16,
+    // Portable float map, keeps the full float values. Only stores 1 or 3 channels
+    Pfm,
+}
+
+// A render target copied back to the CPU. Rows are stored top to bottom like image files,
+// the reverse of OpenGL
+#[derive(Clone, Debug)]
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub data: Vec<f32>,
+}
+
+impl FloatImage {
+    pub fn new(width: u32, height: u32, channels: usize) -> FloatImage {
+        assert!(channels >= 1 && channels <= 4);
+        FloatImage {
+            width,
+            height,
+            channels,
+            data: vec![0.0; width as usize * height as usize * channels],
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &[f32] {
+        let start = (y as usize * self.width as usize + x as usize) * self.channels;
+        &self.data[start..start + self.channels]
+    }
+
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut [f32] {
+        let start = (y as usize * self.width as usize + x as usize) * self.channels;
+        &mut self.data[start..start + self.channels]
+    }
+
+    // Keeps the first `channels` channels of each pixel, padding with zeros if there are fewer
+    pub fn with_channels(&self, channels: usize) -> FloatImage {
+        let mut image = FloatImage::new(self.width, self.height, channels);
+        for (src, dst) in self
+            .data
+            .chunks(self.channels)
+            .zip(image.data.chunks_mut(channels))
+        {
+            for (i, value) in dst.iter_mut().enumerate() {
+                *value = src.get(i).cloned().unwrap_or(0.0);
+            }
+        }
+        image
+    }
+
+    // Linearly maps min..max to 0..1, e.g. to view a linear depth range as a PNG
+    pub fn remap(&self, min: f32, max: f32) -> FloatImage {
+        let scale = 1.0 / (max - min);
+        FloatImage {
+            data: self.data.iter().map(|value| (value - min) * scale).collect(),
+            ..self.clone()
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: ImageFormat) -> Result<(), Box<Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Png => self.encode_png(&mut writer, 8)?,
+            ImageFormat::Png16 => self.encode_png(&mut writer, 16)?,
+            ImageFormat::Pfm => self.encode_pfm(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn encode_png<W: Write>(&self, writer: W, bits: u8) -> Result<(), Box<Error>> {
+        // PNG has no two channel colour type that makes sense here, so the blue channel is zero
+        let image = if self.channels == 2 {
+            self.with_channels(3)
+        } else {
+            self.clone()
+        };
+        let colour = match image.channels {
+            1 => ColorType::Gray(bits),
+            3 => ColorType::RGB(bits),
+            _ => ColorType::RGBA(bits),
+        };
+
+        let bytes = png_samples(&image.data, bits);
+        PNGEncoder::new(writer).encode(&bytes, image.width, image.height, colour)?;
+        Ok(())
+    }
+
+    pub fn encode_pfm<W: Write>(&self, mut writer: W) -> Result<(), Box<Error>> {
+        let image = if self.channels == 1 {
+            self.clone()
+        } else {
+            self.with_channels(3)
+        };
+        let kind = if image.channels == 1 { "Pf" } else { "PF" };
+        // A negative scale marks the data as little endian
+        write!(writer, "{}\n{} {}\n-1.0\n", kind, image.width, image.height)?;
+
+        // PFM stores the bottom row first
+        let row_len = image.width as usize * image.channels;
+        for row in image.data.chunks(row_len.max(1)).rev() {
+            for value in row {
+                writer.write_all(&value.to_bits().to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads the main mip level of a float or normalized texture. Every texture is read as RGBA,
+// with missing channels filled in by OpenGL
+pub fn read_texture(texture: &Texture2d) -> FloatImage {
+    let (width, height) = texture.dimensions();
+    let rect = Rect {
+        left: 0,
+        bottom: 0,
+        width,
+        height,
+    };
+    let rows: Vec<Vec<(f32, f32, f32, f32)>> = texture
+        .main_level()
+        .first_layer()
+        .into_image(None)
+        .expect("2d textures have a single image")
+        .raw_read(&rect);
+
+    let mut image = FloatImage::new(width, height, 4);
+    for (y, row) in rows.iter().rev().enumerate() {
+        for (x, &(r, g, b, a)) in row.iter().enumerate() {
+            image
+                .pixel_mut(x as u32, y as u32)
+                .copy_from_slice(&[r, g, b, a]);
+        }
+    }
+    image
+}
+
+// The samples of a PNG with `bits` per channel
+fn png_samples(data: &[f32], bits: u8) -> Vec<u8> {
+    if bits == 16 {
+        // PNG stores 16 bit samples big endian
+        data.iter()
+            .flat_map(|&value| {
+                let sample = (clamp_unit(value) * 65535.0).round() as u16;
+                vec![(sample >> 8) as u8, sample as u8]
+            })
+            .collect()
+    } else {
+        data.iter()
+            .map(|&value| (clamp_unit(value) * 255.0).round() as u8)
+            .collect()
+    }
+}
+
+fn clamp_unit(value: f32) -> f32 {
+    if value.is_nan() {
+        0.0
+    } else {
+        value.max(0.0).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::png::PNGDecoder;
+    use image::{DecodingResult, ImageDecoder};
+
+    // 2x2 with a different value in every channel, row 0 on top
+    fn image(channels: usize) -> FloatImage {
+        let mut image = FloatImage::new(2, 2, channels);
+        for (i, value) in image.data.iter_mut().enumerate() {
+            *value = i as f32 * 0.0625;
+        }
+        image
+    }
+
+    // The header lines and the floats that follow them
+    fn decode_pfm(bytes: &[u8]) -> (Vec<String>, Vec<f32>) {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while lines.len() < 3 {
+            let end = start + bytes[start..].iter().position(|&b| b == b'\n').unwrap();
+            lines.push(String::from_utf8(bytes[start..end].to_vec()).unwrap());
+            start = end + 1;
+        }
+        let floats = bytes[start..]
+            .chunks(4)
+            .map(|b| f32::from_bits(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect();
+        (lines, floats)
+    }
+
+    // The colour type and the samples, 16 bit samples as their big endian bytes
+    fn decode_png(bytes: &[u8]) -> ((u32, u32), ColorType, Vec<u8>) {
+        let mut decoder = PNGDecoder::new(bytes);
+        let dimensions = decoder.dimensions().unwrap();
+        let colour = decoder.colortype().unwrap();
+        let samples = match decoder.read_image().unwrap() {
+            DecodingResult::U8(samples) => samples,
+            DecodingResult::U16(samples) => samples
+                .iter()
+                .flat_map(|&sample| vec![(sample >> 8) as u8, sample as u8])
+                .collect(),
+        };
+        (dimensions, colour, samples)
+    }
+
+    #[test]
+    fn pfm_is_bottom_up_and_little_endian() {
+        let image = image(3);
+        let mut bytes = Vec::new();
+        image.encode_pfm(&mut bytes).unwrap();
+
+        let (header, floats) = decode_pfm(&bytes);
+        // The negative scale marks little endian data
+        assert_eq!(header, ["PF", "2 2", "-1.0"]);
+        let mut expected = image.pixel(0, 1).to_vec();
+        expected.extend_from_slice(image.pixel(1, 1));
+        expected.extend_from_slice(image.pixel(0, 0));
+        expected.extend_from_slice(image.pixel(1, 0));
+        assert_eq!(floats, expected);
+    }
+
+    #[test]
+    fn pfm_stores_one_or_three_channels() {
+        let mut bytes = Vec::new();
+        image(1).encode_pfm(&mut bytes).unwrap();
+        let (header, floats) = decode_pfm(&bytes);
+        assert_eq!(header[0], "Pf");
+        assert_eq!(floats, [0.125, 0.1875, 0.0, 0.0625]);
+
+        // Two channels are padded with a zero blue, four lose their alpha
+        let mut bytes = Vec::new();
+        image(2).encode_pfm(&mut bytes).unwrap();
+        let (header, floats) = decode_pfm(&bytes);
+        assert_eq!(header[0], "PF");
+        assert_eq!(&floats[..3], [0.25, 0.3125, 0.0]);
+
+        let mut bytes = Vec::new();
+        image(4).encode_pfm(&mut bytes).unwrap();
+        let (_, floats) = decode_pfm(&bytes);
+        assert_eq!(floats.len(), 12);
+        assert_eq!(&floats[..3], [0.5, 0.5625, 0.625]);
+    }
+
+    #[test]
+    fn png_is_clamped_to_8_bits() {
+        let mut image = image(4);
+        image.pixel_mut(1, 1).copy_from_slice(&[-1.0, 2.0, 0.5, ::std::f32::NAN]);
+        let mut bytes = Vec::new();
+        image.encode_png(&mut bytes, 8).unwrap();
+
+        let (dimensions, colour, samples) = decode_png(&bytes);
+        assert_eq!(dimensions, (2, 2));
+        assert_eq!(colour, ColorType::RGBA(8));
+        // PNG is top down like the image
+        assert_eq!(&samples[..4], [0, 16, 32, 48]);
+        assert_eq!(&samples[12..], [0, 255, 128, 0]);
+    }
+
+    #[test]
+    fn png_pads_two_channels() {
+        let mut bytes = Vec::new();
+        image(2).encode_png(&mut bytes, 8).unwrap();
+        let (_, colour, samples) = decode_png(&bytes);
+        assert_eq!(colour, ColorType::RGB(8));
+        assert_eq!(&samples[..6], [0, 16, 0, 32, 48, 0]);
+
+        let mut bytes = Vec::new();
+        image(1).encode_png(&mut bytes, 8).unwrap();
+        let (_, colour, samples) = decode_png(&bytes);
+        assert_eq!(colour, ColorType::Gray(8));
+        assert_eq!(samples, [0, 16, 32, 48]);
+    }
+
+    #[test]
+    fn png16_samples_are_big_endian() {
+        // 0.5 rounds to 32768
+        assert_eq!(png_samples(&[0.5, 1.0, -0.5], 16), [0x80, 0x00, 0xff, 0xff, 0x00, 0x00]);
+
+        // The decoder strips 16 bit samples, so the bit depth is read from the IHDR chunk, after
+        // the 8 byte signature, the chunk length and type and the width and height
+        let mut bytes = Vec::new();
+        image(3).encode_png(&mut bytes, 16).unwrap();
+        assert_eq!(&bytes[12..16], b"IHDR");
+        assert_eq!(bytes[24], 16);
+        let (dimensions, _, _) = decode_png(&bytes);
+        assert_eq!(dimensions, (2, 2));
+    }
+}
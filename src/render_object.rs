@@ -1,7 +1,7 @@
+use assets::{MeshHandle, TextureHandle};
 use glium::texture::Texture2d;
 use glium::VertexBuffer;
 use Vertex;
-use SimpleVertex;
 
 pub trait ModelMatrix {
     fn matrix(&self) -> [[f32; 4]; 4];
@@ -11,6 +11,12 @@ pub trait PosMatrix : ModelMatrix {
     fn position(&self) -> [f32; 3];
 }
 
+impl<'a, T: ModelMatrix> ModelMatrix for &'a T {
+    fn matrix(&self) -> [[f32; 4]; 4] {
+        (*self).matrix()
+    }
+}
+
 pub struct RenderObject<'a, T: ModelMatrix> {
     pub model_matrix: T,
     pub buffer: &'a VertexBuffer<Vertex>,
@@ -19,6 +25,12 @@ pub struct RenderObject<'a, T: ModelMatrix> {
     pub normal_tex: &'a Texture2d,
     pub depth_tex: &'a Texture2d,
     pub depth_scale: f32,
+    // Factors of the metallic-roughness material model, multiplied with `specular_tex`. Default
+    // to 1 and 0 so plain textures give rough dielectrics
+    pub roughness: f32,
+    pub metallic: f32,
+    // Whether the object is drawn into shadow maps. Off unless set
+    pub casts_shadows: bool,
 }
 
 impl<'a, T: ModelMatrix> RenderObject<'a, T> {
@@ -38,23 +50,68 @@ impl<'a, T: ModelMatrix> RenderObject<'a, T> {
             specular_tex,
             normal_tex,
             depth_tex, 
-            depth_scale
+            depth_scale,
+            roughness: 1.0,
+            metallic: 0.0,
+            casts_shadows: false,
         }
     }
 }
 
-pub struct LightModel<'a, T: PosMatrix>  {
-    pub position: T,
-    pub colour: [f32; 3],
-    pub buffer: &'a VertexBuffer<SimpleVertex>
+// Same as `RenderObject` but owns handles from an `AssetManager` instead of borrowing, so it
+// can be stored in long lived scenes
+pub struct SharedRenderObject<T: ModelMatrix> {
+    pub model_matrix: T,
+    pub buffer: MeshHandle,
+    pub diffuse_tex: TextureHandle,
+    pub specular_tex: TextureHandle,
+    pub normal_tex: TextureHandle,
+    pub depth_tex: TextureHandle,
+    pub depth_scale: f32,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub casts_shadows: bool,
 }
 
-impl<'a, T: PosMatrix> LightModel<'a, T> {
-    pub fn new(position: T, colour: [f32; 3], buffer: &'a VertexBuffer<SimpleVertex>) -> LightModel<'a, T> {
-        LightModel {
-            position,
-            colour, 
-            buffer
+impl<T: ModelMatrix> SharedRenderObject<T> {
+    pub fn new(
+        model_matrix: T,
+        buffer: MeshHandle,
+        diffuse_tex: TextureHandle,
+        specular_tex: TextureHandle,
+        normal_tex: TextureHandle,
+        depth_tex: TextureHandle,
+        depth_scale: f32,
+    ) -> SharedRenderObject<T> {
+        SharedRenderObject {
+            model_matrix,
+            buffer,
+            diffuse_tex,
+            specular_tex,
+            normal_tex,
+            depth_tex,
+            depth_scale,
+            roughness: 1.0,
+            metallic: 0.0,
+            casts_shadows: false,
+        }
+    }
+
+    // Borrows the handles so the object can be passed to `PrePass::draw_object`
+    pub fn as_render_object(&self) -> RenderObject<&T> {
+        RenderObject {
+            roughness: self.roughness,
+            metallic: self.metallic,
+            casts_shadows: self.casts_shadows,
+            ..RenderObject::new(
+                &self.model_matrix,
+                &self.buffer,
+                &self.diffuse_tex,
+                &self.specular_tex,
+                &self.normal_tex,
+                &self.depth_tex,
+                self.depth_scale,
+            )
         }
     }
-}
\ No newline at end of file
+}
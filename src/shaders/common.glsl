@@ -0,0 +1,51 @@
+// Helpers shared by the prepass and lighting shaders
+
+const float PI = 3.14159265359;
+
+// Normals are stored as two channel octahedral coordinates in 0..1 so they fit in unsigned
+// formats. `oct_encode` and `oct_decode` in math.rs are the CPU versions of these
+vec2 sign_not_zero(vec2 v) {
+    return vec2(v.x >= 0.0 ? 1.0 : -1.0, v.y >= 0.0 ? 1.0 : -1.0);
+}
+
+vec2 encode_normal(vec3 normal) {
+    vec2 p = normal.xy / (abs(normal.x) + abs(normal.y) + abs(normal.z));
+    if (normal.z < 0.0) {
+        p = (1.0 - abs(p.yx)) * sign_not_zero(p);
+    }
+    return p * 0.5 + 0.5;
+}
+
+vec3 decode_normal(vec2 encoded) {
+    vec2 p = encoded * 2.0 - 1.0;
+    vec3 normal = vec3(p, 1.0 - abs(p.x) - abs(p.y));
+    if (normal.z < 0.0) {
+        normal.xy = (1.0 - abs(normal.yx)) * sign_not_zero(normal.xy);
+    }
+    return normalize(normal);
+}
+
+// Reconstructs the world position of a pixel from its depth buffer value. T1 and T2 are the
+// (2, 2) and (2, 3) terms of the projection matrix and inv_projection is the inverse of the
+// view projection matrix
+vec3 reconstruct_position(float depth, vec2 frag_coord, float T1, float T2, mat4 inv_projection) {
+    vec3 ndcspace = vec3(frag_coord * 2.0 - 1.0, depth * 2.0 - 1.0);
+    float clipspace_w = T2 / (ndcspace.z + T1);
+    vec4 clipspace = vec4(ndcspace * clipspace_w, clipspace_w);
+    return (inv_projection * clipspace).xyz;
+}
+
+// Distance from the camera plane of a depth buffer value, with T1 and T2 as above
+float linear_depth(float depth, float T1, float T2, bool orthographic) {
+    float ndc = depth * 2.0 - 1.0;
+    if (orthographic) {
+        return (T2 - ndc) / T1;
+    }
+    return T2 / (ndc + T1);
+}
+
+// Texture coordinates of a direction in an equirectangular panorama, with +y at the top
+vec2 equirect_coords(vec3 direction) {
+    vec3 d = normalize(direction);
+    return vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, asin(clamp(d.y, -1.0, 1.0)) / PI + 0.5);
+}
@@ -0,0 +1,73 @@
+#version 440
+
+#include "common.glsl"
+
+// Matches `DebugView` in debug.rs
+const int ALBEDO = 0;
+const int NORMALS = 1;
+const int SPECULAR = 2;
+const int DEPTH = 3;
+const int POSITION = 4;
+const int LIGHT = 5;
+const int GRID = 6;
+
+uniform sampler2D diffuse_tex;
+uniform sampler2D normal_tex;
+uniform sampler2D specular_tex;
+uniform sampler2D depth_tex;
+uniform sampler2D light_tex;
+
+uniform int view;
+uniform float depth_near;
+uniform float depth_far;
+uniform float position_range;
+
+uniform float T1;
+uniform float T2;
+uniform bool orthographic;
+uniform mat4 inv_projection;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+vec3 channel(int view, vec2 coord) {
+    if (view == ALBEDO) {
+        return texture(diffuse_tex, coord).rgb;
+    }
+    if (view == NORMALS) {
+        return decode_normal(texture(normal_tex, coord).xy) * 0.5 + 0.5;
+    }
+    if (view == SPECULAR) {
+        return texture(specular_tex, coord).rgb;
+    }
+
+    float depth = texture(depth_tex, coord).r;
+    if (view == DEPTH) {
+        float eye_depth = linear_depth(depth, T1, T2, orthographic);
+        return vec3(clamp((eye_depth - depth_near) / (depth_far - depth_near), 0.0, 1.0));
+    }
+    if (view == POSITION) {
+        // Nothing was drawn where the depth is still cleared
+        if (depth >= 1.0) {
+            return vec3(0.0);
+        }
+        vec3 position = reconstruct_position(depth, coord, T1, T2, inv_projection);
+        return clamp(position / position_range * 0.5 + 0.5, 0.0, 1.0);
+    }
+    return texture(light_tex, coord).rgb;
+}
+
+void main() {
+    if (view != GRID) {
+        colour = vec4(channel(view, f_tex), 1.0);
+        return;
+    }
+
+    // Albedo, normals and specular on the top row, depth, position and light on the bottom
+    vec2 cells = vec2(3.0, 2.0);
+    vec2 cell = floor(f_tex * cells);
+    vec2 coord = fract(f_tex * cells);
+    int cell_view = int(cell.x) + (1 - int(cell.y)) * 3;
+    colour = vec4(channel(cell_view, coord), 1.0);
+}
@@ -0,0 +1,10 @@
+#version 440
+
+// A single triangle covering the screen, drawn with 3 empty vertices
+
+out vec2 f_tex;
+
+void main() {
+    f_tex = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    gl_Position = vec4(f_tex * 2.0 - 1.0, 0.0, 1.0);
+}
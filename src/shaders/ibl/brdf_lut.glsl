@@ -0,0 +1,40 @@
+#version 440
+
+#include "common.glsl"
+#include "ibl/common.glsl"
+
+// The split sum BRDF lookup table: the scale and bias applied to F0 for each N.V (x) and
+// roughness (y)
+
+in vec2 f_tex;
+
+uniform int sample_count;
+
+out vec2 colour;
+
+void main() {
+    float n_dot_v = max(f_tex.x, 0.001);
+    float roughness = f_tex.y;
+    vec3 view = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    vec3 normal = vec3(0.0, 0.0, 1.0);
+
+    float scale = 0.0;
+    float bias = 0.0;
+    uint count = uint(sample_count);
+    for (uint i = 0u; i < count; i++) {
+        vec3 h = importance_sample_ggx(hammersley(i, count), normal, roughness);
+        vec3 l = normalize(2.0 * dot(view, h) * h - view);
+        float n_dot_l = max(l.z, 0.0);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        float n_dot_h = max(h.z, 0.0);
+        float v_dot_h = max(dot(view, h), 0.0);
+        float visibility = geometry_smith_ibl(n_dot_v, n_dot_l, roughness) * v_dot_h / (n_dot_h * n_dot_v);
+        float fresnel = pow(1.0 - v_dot_h, 5.0);
+        scale += (1.0 - fresnel) * visibility;
+        bias += fresnel * visibility;
+    }
+    colour = vec2(scale, bias) / float(sample_count);
+}
@@ -0,0 +1,39 @@
+// Helpers for baking environment maps, see ibl.rs. Needs common.glsl
+
+// The cube map face being drawn, see `cube_faces` in ibl.rs
+uniform vec3 face_forward;
+uniform vec3 face_right;
+uniform vec3 face_up;
+
+// The direction of a texel of the face being drawn
+vec3 face_direction(vec2 tex_coord) {
+    vec2 p = tex_coord * 2.0 - 1.0;
+    return normalize(face_forward + face_right * p.x + face_up * p.y);
+}
+
+// Low discrepancy point `i` of `count` in 0..1
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), float(bitfieldReverse(i)) * 2.3283064365386963e-10);
+}
+
+// A half vector around `normal` distributed like the GGX lobe of `roughness`
+vec3 importance_sample_ggx(vec2 xi, vec3 normal, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    vec3 h = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + normal * h.z);
+}
+
+// Smith geometry term with k remapped for image based lighting
+float geometry_smith_ibl(float n_dot_v, float n_dot_l, float roughness) {
+    float k = roughness * roughness / 2.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
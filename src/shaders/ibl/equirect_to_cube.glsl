@@ -0,0 +1,14 @@
+#version 440
+
+#include "common.glsl"
+#include "ibl/common.glsl"
+
+in vec2 f_tex;
+
+uniform sampler2D equirect_tex;
+
+out vec4 colour;
+
+void main() {
+    colour = vec4(texture(equirect_tex, equirect_coords(face_direction(f_tex))).rgb, 1.0);
+}
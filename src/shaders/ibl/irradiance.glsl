@@ -0,0 +1,36 @@
+#version 440
+
+#include "common.glsl"
+#include "ibl/common.glsl"
+
+// Convolves the environment with a cosine lobe, giving the diffuse light reaching a surface
+// facing each direction
+
+in vec2 f_tex;
+
+uniform samplerCube environment;
+// A mip level of the environment about as detailed as the steps below
+uniform float source_lod;
+
+out vec4 colour;
+
+const float STEP = 0.025;
+
+void main() {
+    vec3 normal = face_direction(f_tex);
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    vec3 irradiance = vec3(0.0);
+    float count = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += STEP) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += STEP) {
+            vec3 tangent_sample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = tangent_sample.x * right + tangent_sample.y * up + tangent_sample.z * normal;
+            irradiance += textureLod(environment, direction, source_lod).rgb * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    colour = vec4(PI * irradiance / count, 1.0);
+}
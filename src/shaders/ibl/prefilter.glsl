@@ -0,0 +1,51 @@
+#version 440
+
+#include "common.glsl"
+#include "lighting/brdf.glsl"
+#include "ibl/common.glsl"
+
+// Convolves the environment with the GGX lobe of one roughness, assuming the view direction
+// equals the normal. Each mip level of the prefiltered map holds a higher roughness
+
+in vec2 f_tex;
+
+uniform samplerCube environment;
+uniform float roughness;
+uniform int sample_count;
+// Width of the environment's main level
+uniform float source_size;
+
+out vec4 colour;
+
+void main() {
+    vec3 normal = face_direction(f_tex);
+    vec3 view = normal;
+
+    vec3 total = vec3(0.0);
+    float weight = 0.0;
+    uint count = uint(sample_count);
+    for (uint i = 0u; i < count; i++) {
+        vec3 h = importance_sample_ggx(hammersley(i, count), normal, roughness);
+        vec3 l = normalize(2.0 * dot(view, h) * h - view);
+        float n_dot_l = dot(normal, l);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        // Reads a blurrier mip for samples covering more of the sphere, which hides the
+        // bright speckles of undersampled environments
+        float lod = 0.0;
+        if (roughness > 0.0) {
+            float n_dot_h = max(dot(normal, h), 0.0);
+            float h_dot_v = max(dot(h, view), 0.0);
+            float pdf = distribution_ggx(n_dot_h, roughness) * n_dot_h / (4.0 * h_dot_v) + 0.0001;
+            float texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+            float sample_solid_angle = 1.0 / (float(sample_count) * pdf + 0.0001);
+            lod = 0.5 * log2(sample_solid_angle / texel_solid_angle);
+        }
+
+        total += textureLod(environment, l, max(lod, 0.0)).rgb * n_dot_l;
+        weight += n_dot_l;
+    }
+    colour = vec4(total / max(weight, 0.0001), 1.0);
+}
@@ -0,0 +1,57 @@
+#version 440
+
+#include "common.glsl"
+#include "lighting/shade.glsl"
+#include "lighting/surface.glsl"
+
+// Image based ambient lighting from an `Environment`, see `LightingPass::draw_ambient`
+
+in vec2 f_tex;
+
+uniform float shininess;
+
+uniform samplerCube irradiance_map;
+uniform samplerCube prefiltered_map;
+uniform sampler2D brdf_lut;
+// The last mip level of `prefiltered_map`, which holds roughness 1
+uniform float prefiltered_lod;
+uniform float ambient_intensity;
+
+#ifdef SSAO
+// From `Ssao`, 1 where nothing is occluded
+uniform sampler2D ao_tex;
+#endif
+
+out vec4 colour;
+
+void main() {
+    float depth = texture(depth_tex, f_tex).r;
+    // The background is left to the sky
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    SurfaceData surface = read_surface(f_tex, depth);
+    vec3 reflected = reflect(-surface.view_dir, surface.normal);
+    float n_dot_v = max(dot(surface.normal, surface.view_dir), 0.0);
+    vec3 irradiance = texture(irradiance_map, surface.normal).rgb;
+
+#ifdef METALLIC_ROUGHNESS
+    vec3 fresnel = fresnel_schlick_roughness(n_dot_v, base_reflectance(surface), surface.roughness);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * irradiance * surface.diffuse;
+    vec3 prefiltered = textureLod(prefiltered_map, reflected, surface.roughness * prefiltered_lod).rgb;
+    vec2 brdf = texture(brdf_lut, vec2(n_dot_v, surface.roughness)).rg;
+    vec3 ambient = (diffuse + prefiltered * (fresnel * brdf.x + brdf.y)) * surface.ao;
+#else
+    // The roughness whose highlight is about as wide as a Blinn-Phong one of `shininess`
+    float roughness = sqrt(2.0 / (shininess + 2.0));
+    vec3 prefiltered = textureLod(prefiltered_map, reflected, roughness * prefiltered_lod).rgb;
+    vec3 ambient = irradiance * surface.diffuse + prefiltered * surface.specular;
+#endif
+
+#ifdef SSAO
+    ambient *= texture(ao_tex, f_tex).r;
+#endif
+
+    colour = vec4(ambient * ambient_intensity, 1.0);
+}
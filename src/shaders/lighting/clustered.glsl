@@ -0,0 +1,76 @@
+#version 440
+
+#include "common.glsl"
+#include "lighting/shade.glsl"
+#include "lighting/surface.glsl"
+
+// Shades every light of the pixel's cluster, see `ClusteredLights` in light.rs
+
+in vec2 f_tex;
+
+uniform float shininess;
+uniform bool orthographic;
+
+// Tiles across, tiles up and depth slices
+uniform ivec3 cluster_dims;
+uniform float znear;
+uniform float zfar;
+
+// 6 texels per light, see `Light::pack`
+uniform samplerBuffer light_data;
+// Offset into `cluster_indices` and light count of each cluster
+uniform usamplerBuffer cluster_offsets;
+uniform usamplerBuffer cluster_indices;
+
+out vec4 colour;
+
+LightData fetch_light(int index) {
+    int base = index * 6;
+    vec4 position_radius = texelFetch(light_data, base);
+    vec4 colour_kind = texelFetch(light_data, base + 1);
+    vec4 direction_attenuation = texelFetch(light_data, base + 2);
+    vec4 cone_area = texelFetch(light_data, base + 3);
+
+    return LightData(
+        int(colour_kind.w + 0.5),
+        position_radius.xyz,
+        direction_attenuation.xyz,
+        colour_kind.rgb,
+        position_radius.w,
+        int(direction_attenuation.w + 0.5),
+        cone_area.x,
+        cone_area.y,
+        int(cone_area.z + 0.5),
+        cone_area.w,
+        texelFetch(light_data, base + 4).xyz,
+        texelFetch(light_data, base + 5).xyz
+    );
+}
+
+// Matches `ClusterGrid::slice` and `ClusterGrid::index` in cluster.rs
+int cluster_index(vec2 screen, float view_depth) {
+    float fraction = log(max(view_depth, znear) / znear) / log(zfar / znear);
+    int slice = clamp(int(fraction * float(cluster_dims.z)), 0, cluster_dims.z - 1);
+    ivec2 tile = clamp(ivec2(screen * vec2(cluster_dims.xy)), ivec2(0), cluster_dims.xy - 1);
+    return tile.x + tile.y * cluster_dims.x + slice * cluster_dims.x * cluster_dims.y;
+}
+
+void main() {
+    float depth = texture(depth_tex, f_tex).r;
+    // The background has no surface to light
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    SurfaceData surface = read_surface(f_tex, depth);
+
+    int cluster = cluster_index(f_tex, linear_depth(depth, T1, T2, orthographic));
+    uvec2 range = texelFetch(cluster_offsets, cluster).xy;
+
+    vec3 total = vec3(0.0);
+    for (uint i = 0u; i < range.y; i++) {
+        int light = int(texelFetch(cluster_indices, int(range.x + i)).r);
+        total += shade(fetch_light(light), surface, shininess);
+    }
+    colour = vec4(total, 1.0);
+}
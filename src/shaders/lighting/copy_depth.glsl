@@ -0,0 +1,9 @@
+#version 440
+
+uniform sampler2D depth_tex;
+
+in vec2 f_tex;
+
+void main() {
+    gl_FragDepth = texture(depth_tex, f_tex).r;
+}
@@ -1,46 +1,69 @@
 #version 440
 
-uniform sampler2D diffuse_tex;
-uniform sampler2D specular_tex;
-uniform sampler2D normal_tex;
-uniform sampler2D depth_tex;
+#include "common.glsl"
+#include "lighting/shade.glsl"
+#include "lighting/surface.glsl"
+
+// Set by `lighting_defines` to match the light's `ShadowMap`, only one of them at a time
+#ifdef CUBE_SHADOWS
+#include "lighting/shadow_cube.glsl"
+#else
+#ifdef SHADOWS
+#include "lighting/shadow.glsl"
+#endif
+#endif
 
 uniform float shininess;
 
-uniform float T1;
-uniform float T2;
-uniform mat4 inv_projection;
+uniform int light_type;
 uniform vec3 light_pos;
+uniform vec3 light_dir;
 uniform vec3 light_colour;
-uniform vec3 eye;
-
+uniform float light_radius;
+uniform int attenuation;
+uniform float cos_inner;
+uniform float cos_outer;
+uniform int area_shape;
+uniform float area_radius;
+uniform vec3 area_right;
+uniform vec3 area_up;
 
 out vec4 colour;
 
-vec3 frag_position(vec2 frag_coord) {
-    float depth = texture(depth_tex, frag_coord) * 2.0 - 1.0;
-    vec3 ndcspace = vec3(frag_coord * 2.0 - 1.0, depth);
-    float clipspace_w = T2 / (ndcspace.z + T1);
-    vec4 clipspace = vec4(ndcspace * clipspace_w, clipspace_w);
-    return (inv_projection * clipspace).xyz;
-}
-
 void main() {
-    vec2 size = textureSize(depth_tex, 0.0);
+    vec2 size = vec2(textureSize(depth_tex, 0));
     vec2 frag_coord = gl_FragCoord.xy / size;
-    vec3 frag_pos = frag_position(frag_coord);
-    vec3 light_dir = normalize(light_pos - frag_pos);
-    vec3 view_dir = normalize(eye_pos - frag_pos);
-    vec3 half_way_dir = normalize(light_dir + view_dir);
-
-    vec3 normal = normalize(texture(normal_tex, f_tex) * 2.0 - 1.0);
-    vec3 diffuse_colour = texture(diffuse_tex, f_tex);
-    vec3 specular_colour = texture(specular_tex, f_tex);
+    float depth = texture(depth_tex, frag_coord).r;
+    // The background has no surface to light
+    if (depth >= 1.0) {
+        discard;
+    }
 
-    float diffuse_value = max(dot(light_dir, normal), 0.0);
-    float spec = pow(max(dot(normal, half_way_dir), 0.0), shininess);
+    SurfaceData surface = read_surface(frag_coord, depth);
 
-    colour = vec4((diffuse_value * diffuse_colour + spec * specular_colour) * light_colour, 1.0);
+    LightData light = LightData(
+        light_type,
+        light_pos,
+        light_dir,
+        light_colour,
+        light_radius,
+        attenuation,
+        cos_inner,
+        cos_outer,
+        area_shape,
+        area_radius,
+        area_right,
+        area_up
+    );
 
+    float shadow = 1.0;
+#ifdef CUBE_SHADOWS
+    shadow = shadow_factor(surface.position, surface.normal);
+#else
+#ifdef SHADOWS
+    shadow = shadow_factor(surface.position, surface.normal);
+#endif
+#endif
 
-}
\ No newline at end of file
+    colour = vec4(shade(light, surface, shininess) * shadow, 1.0);
+}
@@ -0,0 +1,141 @@
+// Shading of one light, shared by the per-light and clustered lighting passes. Blinn-Phong with
+// the global `shininess`, or Cook-Torrance when METALLIC_ROUGHNESS is defined
+
+#include "lighting/brdf.glsl"
+
+// Match `Light`, `Attenuation` and `AreaShape` in light.rs
+const int POINT = 0;
+const int DIRECTIONAL = 1;
+const int SPOT = 2;
+const int AREA = 3;
+
+const int NO_ATTENUATION = 0;
+const int LINEAR = 1;
+const int INVERSE_SQUARE = 2;
+
+const int SPHERE = 0;
+const int RECTANGLE = 1;
+
+struct LightData {
+    int kind;
+    vec3 position;
+    // The direction the light travels in, for directional and spot lights
+    vec3 direction;
+    vec3 colour;
+    float radius;
+    int attenuation;
+    float cos_inner;
+    float cos_outer;
+    int area_shape;
+    float area_radius;
+    vec3 area_right;
+    vec3 area_up;
+};
+
+// `specular` is only used by Blinn-Phong, `metallic`, `roughness` and `ao` by the
+// metallic-roughness model, where `diffuse` is the albedo
+struct SurfaceData {
+    vec3 position;
+    vec3 normal;
+    vec3 view_dir;
+    vec3 diffuse;
+    vec3 specular;
+    float metallic;
+    float roughness;
+    float ao;
+};
+
+float falloff(LightData light, float distance_to_light) {
+    float ratio = clamp(distance_to_light / light.radius, 0.0, 1.0);
+    if (light.attenuation == NO_ATTENUATION) {
+        return ratio < 1.0 ? 1.0 : 0.0;
+    }
+    if (light.attenuation == LINEAR) {
+        return 1.0 - ratio;
+    }
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    // The + 1 keeps the light finite at its centre
+    return window * window / (distance_to_light * distance_to_light + 1.0);
+}
+
+// The point of an area light's rectangle closest to `point`
+vec3 clamp_to_rectangle(LightData light, vec3 point) {
+    vec3 offset = point - light.position;
+    float half_width = length(light.area_right);
+    float half_height = length(light.area_up);
+    vec3 right = light.area_right / half_width;
+    vec3 up = light.area_up / half_height;
+    float x = clamp(dot(offset, right), -half_width, half_width);
+    float y = clamp(dot(offset, up), -half_height, half_height);
+    return light.position + right * x + up * y;
+}
+
+// Reflectance at normal incidence, 4% for dielectrics and the albedo for metals
+vec3 base_reflectance(SurfaceData surface) {
+    return mix(vec3(0.04), surface.diffuse, surface.metallic);
+}
+
+vec3 shade(LightData light, SurfaceData surface, float shininess) {
+    vec3 reflected = reflect(-surface.view_dir, surface.normal);
+
+    // Vectors from the surface to the points used for diffuse and specular lighting
+    vec3 diffuse_vec;
+    vec3 specular_vec;
+    float strength = 1.0;
+
+    if (light.kind == DIRECTIONAL) {
+        diffuse_vec = -light.direction;
+        specular_vec = -light.direction;
+    } else {
+        vec3 centre = light.position - surface.position;
+        diffuse_vec = centre;
+        specular_vec = centre;
+
+        if (light.kind == AREA && light.area_shape == SPHERE) {
+            vec3 centre_to_ray = dot(centre, reflected) * reflected - centre;
+            specular_vec = centre + centre_to_ray * clamp(light.area_radius / length(centre_to_ray), 0.0, 1.0);
+        } else if (light.kind == AREA && light.area_shape == RECTANGLE) {
+            diffuse_vec = clamp_to_rectangle(light, surface.position) - surface.position;
+            vec3 plane_normal = normalize(cross(light.area_right, light.area_up));
+            float facing = dot(reflected, plane_normal);
+            vec3 hit = surface.position;
+            if (abs(facing) > 0.0001) {
+                hit += reflected * max(dot(centre, plane_normal) / facing, 0.0);
+            }
+            specular_vec = clamp_to_rectangle(light, hit) - surface.position;
+        }
+
+        strength = falloff(light, length(diffuse_vec));
+        if (light.kind == SPOT) {
+            float cos_angle = dot(normalize(-centre), light.direction);
+            strength *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+        }
+    }
+
+    vec3 light_vec = normalize(diffuse_vec);
+    vec3 half_way_dir = normalize(normalize(specular_vec) + surface.view_dir);
+
+#ifdef METALLIC_ROUGHNESS
+    // Very low roughness makes the highlight of a point light vanish
+    float roughness = max(surface.roughness, 0.04);
+    float n_dot_l = max(dot(surface.normal, light_vec), 0.0);
+    float n_dot_v = max(dot(surface.normal, surface.view_dir), 0.0001);
+    float n_dot_h = max(dot(surface.normal, half_way_dir), 0.0);
+
+    vec3 fresnel = fresnel_schlick(max(dot(half_way_dir, surface.view_dir), 0.0), base_reflectance(surface));
+    float specular_n_dot_l = max(dot(surface.normal, normalize(specular_vec)), 0.0);
+    vec3 specular = distribution_ggx(n_dot_h, roughness)
+        * geometry_smith(n_dot_v, specular_n_dot_l, roughness)
+        * fresnel / max(4.0 * n_dot_v * specular_n_dot_l, 0.0001);
+
+    // Metals have no diffuse reflection
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * surface.diffuse / PI;
+
+    return (diffuse * n_dot_l + specular * specular_n_dot_l) * light.colour * strength;
+#else
+    float diffuse_value = max(dot(light_vec, surface.normal), 0.0);
+    float spec = pow(max(dot(surface.normal, half_way_dir), 0.0), shininess);
+
+    return (diffuse_value * surface.diffuse + spec * surface.specular) * light.colour * strength;
+#endif
+}
@@ -0,0 +1,28 @@
+// Percentage closer filtering of a directional or spot light's shadow map, see `ShadowMap`
+
+uniform sampler2D shadow_map;
+uniform mat4 shadow_matrix;
+uniform float shadow_bias;
+uniform float shadow_normal_bias;
+uniform int shadow_pcf_radius;
+
+// 1 where the surface is lit, 0 where it is in shadow
+float shadow_factor(vec3 position, vec3 normal) {
+    vec4 clip = shadow_matrix * vec4(position + normal * shadow_normal_bias, 1.0);
+    vec3 coords = clip.xyz / clip.w * 0.5 + 0.5;
+    // Nothing outside the map casts shadows
+    if (coords.z > 1.0 || any(lessThan(coords.xy, vec2(0.0))) || any(greaterThan(coords.xy, vec2(1.0)))) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0));
+    float lit = 0.0;
+    for (int x = -shadow_pcf_radius; x <= shadow_pcf_radius; x++) {
+        for (int y = -shadow_pcf_radius; y <= shadow_pcf_radius; y++) {
+            float closest = texture(shadow_map, coords.xy + vec2(x, y) * texel).r;
+            lit += coords.z - shadow_bias > closest ? 0.0 : 1.0;
+        }
+    }
+    float width = float(shadow_pcf_radius * 2 + 1);
+    return lit / (width * width);
+}
@@ -0,0 +1,39 @@
+// Percentage closer filtering of a point or area light's shadow cube map, see `ShadowMap`
+
+uniform samplerCube shadow_cube;
+uniform vec3 shadow_light_pos;
+uniform float shadow_far;
+uniform float shadow_bias;
+uniform float shadow_normal_bias;
+uniform int shadow_pcf_radius;
+
+// Spread out directions to offset the lookup by, as a cube map has no single texel grid
+const vec3 PCF_OFFSETS[20] = vec3[](
+    vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+    vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+    vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+    vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+    vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+// 1 where the surface is lit, 0 where it is in shadow
+float shadow_factor(vec3 position, vec3 normal) {
+    vec3 offset = position + normal * shadow_normal_bias - shadow_light_pos;
+    float current = length(offset) / shadow_far;
+    if (current > 1.0) {
+        return 1.0;
+    }
+
+    if (shadow_pcf_radius == 0) {
+        return current - shadow_bias > texture(shadow_cube, offset).r ? 0.0 : 1.0;
+    }
+
+    // About `shadow_pcf_radius` texels at the distance of the surface
+    float spread = length(offset) * 2.0 * float(shadow_pcf_radius) / float(textureSize(shadow_cube, 0).x);
+    float lit = 0.0;
+    for (int i = 0; i < 20; i++) {
+        float closest = texture(shadow_cube, offset + PCF_OFFSETS[i] * spread).r;
+        lit += current - shadow_bias > closest ? 0.0 : 1.0;
+    }
+    return lit / 20.0;
+}
@@ -0,0 +1,7 @@
+#version 440
+
+// Used with lighting/vertex.glsl to mark light volumes in the stencil buffer, colour writes are
+// masked off
+
+void main() {
+}
@@ -0,0 +1,37 @@
+// Reads the G-buffer into a `SurfaceData`, shared by the lighting passes. Needs common.glsl and
+// lighting/shade.glsl
+
+uniform sampler2D diffuse_tex;
+uniform sampler2D normal_tex;
+uniform sampler2D depth_tex;
+#ifdef METALLIC_ROUGHNESS
+uniform sampler2D material_tex;
+#else
+uniform sampler2D specular_tex;
+#endif
+
+uniform float T1;
+uniform float T2;
+uniform mat4 inv_projection;
+uniform vec3 eye;
+
+SurfaceData read_surface(vec2 frag_coord, float depth) {
+    SurfaceData surface;
+    surface.position = reconstruct_position(depth, frag_coord, T1, T2, inv_projection);
+    surface.normal = decode_normal(texture(normal_tex, frag_coord).xy);
+    surface.view_dir = normalize(eye - surface.position);
+    surface.diffuse = texture(diffuse_tex, frag_coord).rgb;
+#ifdef METALLIC_ROUGHNESS
+    vec3 material = texture(material_tex, frag_coord).rgb;
+    surface.specular = vec3(0.0);
+    surface.metallic = material.r;
+    surface.roughness = material.g;
+    surface.ao = material.b;
+#else
+    surface.specular = texture(specular_tex, frag_coord).rgb;
+    surface.metallic = 0.0;
+    surface.roughness = 1.0;
+    surface.ao = 1.0;
+#endif
+    return surface;
+}
@@ -0,0 +1,19 @@
+#version 440
+
+// Moves the adapted luminance towards the average luminance of this frame
+
+uniform sampler2D luminance_tex;
+uniform float luminance_lod;
+uniform sampler2D previous_tex;
+// Weight of this frame, 1 on the first frame
+uniform float blend;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    float average = exp(textureLod(luminance_tex, vec2(0.5), luminance_lod).r);
+    float previous = texture(previous_tex, vec2(0.5)).r;
+    colour = vec4(mix(previous, average, blend), 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 440
+
+#include "post/bloom_filters.glsl"
+
+// Adds the top of the bloom chain back onto the source
+
+uniform sampler2D source_tex;
+uniform sampler2D bloom_tex;
+uniform float radius;
+// The intensity over the number of levels summed into `bloom_tex`
+uniform float bloom_scale;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    vec3 bloom = upsample_tent(bloom_tex, f_tex, radius);
+    colour = vec4(texture(source_tex, f_tex).rgb + bloom * bloom_scale, 1.0);
+}
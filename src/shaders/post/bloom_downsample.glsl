@@ -0,0 +1,34 @@
+#version 440
+
+#include "post/bloom_filters.glsl"
+
+// One step down the bloom chain. The first step is compiled with BLOOM_THRESHOLD and only keeps
+// the light above the threshold
+
+uniform sampler2D source_tex;
+
+#ifdef BLOOM_THRESHOLD
+uniform float threshold;
+// Width of the curve easing into the threshold, 0 for a hard cut
+uniform float knee;
+
+vec3 soft_threshold(vec3 colour) {
+    float brightness = max(colour.r, max(colour.g, colour.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 0.00001);
+    return colour * contribution;
+}
+#endif
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    vec3 result = downsample_13(source_tex, f_tex);
+#ifdef BLOOM_THRESHOLD
+    result = soft_threshold(result);
+#endif
+    colour = vec4(result, 1.0);
+}
@@ -0,0 +1,42 @@
+// The filters of the bloom chain, see `Bloom` in post.rs
+
+// Jimenez's 13 tap downsample. Five overlapping 2x2 boxes, the centre one weighted most, which
+// keeps bright pixels from flickering as they move across texels
+vec3 downsample_13(sampler2D tex, vec2 uv) {
+    vec2 texel = 1.0 / vec2(textureSize(tex, 0));
+
+    vec3 a = texture(tex, uv + texel * vec2(-2.0, 2.0)).rgb;
+    vec3 b = texture(tex, uv + texel * vec2(0.0, 2.0)).rgb;
+    vec3 c = texture(tex, uv + texel * vec2(2.0, 2.0)).rgb;
+    vec3 d = texture(tex, uv + texel * vec2(-2.0, 0.0)).rgb;
+    vec3 e = texture(tex, uv).rgb;
+    vec3 f = texture(tex, uv + texel * vec2(2.0, 0.0)).rgb;
+    vec3 g = texture(tex, uv + texel * vec2(-2.0, -2.0)).rgb;
+    vec3 h = texture(tex, uv + texel * vec2(0.0, -2.0)).rgb;
+    vec3 i = texture(tex, uv + texel * vec2(2.0, -2.0)).rgb;
+    vec3 j = texture(tex, uv + texel * vec2(-1.0, 1.0)).rgb;
+    vec3 k = texture(tex, uv + texel * vec2(1.0, 1.0)).rgb;
+    vec3 l = texture(tex, uv + texel * vec2(-1.0, -1.0)).rgb;
+    vec3 m = texture(tex, uv + texel * vec2(1.0, -1.0)).rgb;
+
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// A 3x3 tent filter `radius` texels of `tex` wide
+vec3 upsample_tent(sampler2D tex, vec2 uv, float radius) {
+    vec2 offset = radius / vec2(textureSize(tex, 0));
+
+    vec3 sum = texture(tex, uv).rgb * 4.0;
+    sum += texture(tex, uv + vec2(-offset.x, 0.0)).rgb * 2.0;
+    sum += texture(tex, uv + vec2(offset.x, 0.0)).rgb * 2.0;
+    sum += texture(tex, uv + vec2(0.0, -offset.y)).rgb * 2.0;
+    sum += texture(tex, uv + vec2(0.0, offset.y)).rgb * 2.0;
+    sum += texture(tex, uv + vec2(-offset.x, -offset.y)).rgb;
+    sum += texture(tex, uv + vec2(offset.x, -offset.y)).rgb;
+    sum += texture(tex, uv + vec2(-offset.x, offset.y)).rgb;
+    sum += texture(tex, uv + vec2(offset.x, offset.y)).rgb;
+    return sum / 16.0;
+}
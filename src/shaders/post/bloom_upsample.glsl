@@ -0,0 +1,18 @@
+#version 440
+
+#include "post/bloom_filters.glsl"
+
+// One step up the bloom chain, adding the blurred coarser level to this one
+
+uniform sampler2D current_tex;
+uniform sampler2D coarser_tex;
+uniform float radius;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    vec3 result = texture(current_tex, f_tex).rgb + upsample_tent(coarser_tex, f_tex, radius);
+    colour = vec4(result, 1.0);
+}
@@ -0,0 +1,60 @@
+#version 440
+
+// The "FXAA console" variant: one edge search along the local gradient. Expects tone mapped
+// colours since the edge detection runs on luma
+
+uniform sampler2D source_tex;
+uniform float span_max;
+uniform float reduce_mul;
+uniform float reduce_min;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+float luma(vec3 rgb) {
+    return dot(rgb, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(source_tex, 0));
+
+    vec3 rgb_nw = texture(source_tex, f_tex + vec2(-1.0, -1.0) * texel).rgb;
+    vec3 rgb_ne = texture(source_tex, f_tex + vec2(1.0, -1.0) * texel).rgb;
+    vec3 rgb_sw = texture(source_tex, f_tex + vec2(-1.0, 1.0) * texel).rgb;
+    vec3 rgb_se = texture(source_tex, f_tex + vec2(1.0, 1.0) * texel).rgb;
+    vec3 rgb_m = texture(source_tex, f_tex).rgb;
+
+    float luma_nw = luma(rgb_nw);
+    float luma_ne = luma(rgb_ne);
+    float luma_sw = luma(rgb_sw);
+    float luma_se = luma(rgb_se);
+    float luma_m = luma(rgb_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    float inv_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * inv_dir_min, vec2(-span_max), vec2(span_max)) * texel;
+
+    vec3 rgb_a = 0.5 * (
+        texture(source_tex, f_tex + dir * (1.0 / 3.0 - 0.5)).rgb +
+        texture(source_tex, f_tex + dir * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        texture(source_tex, f_tex + dir * -0.5).rgb +
+        texture(source_tex, f_tex + dir * 0.5).rgb
+    );
+
+    float luma_b = luma(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        colour = vec4(rgb_a, 1.0);
+    } else {
+        colour = vec4(rgb_b, 1.0);
+    }
+}
@@ -0,0 +1,13 @@
+#version 440
+
+uniform sampler2D source_tex;
+uniform float gamma;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    vec3 rgb = max(texture(source_tex, f_tex).rgb, 0.0);
+    colour = vec4(pow(rgb, vec3(1.0 / gamma)), 1.0);
+}
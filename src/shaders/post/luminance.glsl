@@ -0,0 +1,16 @@
+#version 440
+
+// Log luminance of the light buffer. Averaging it down the mip chain gives the log-average
+// luminance of the frame in the smallest mip
+
+uniform sampler2D source_tex;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    vec3 rgb = texture(source_tex, f_tex).rgb;
+    float luminance = dot(rgb, vec3(0.2126, 0.7152, 0.0722));
+    colour = vec4(log(max(luminance, 0.0001)), 0.0, 0.0, 1.0);
+}
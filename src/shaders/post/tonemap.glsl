@@ -0,0 +1,73 @@
+#version 440
+
+// Matches `ToneMapOperator` in post.rs
+const int REINHARD = 0;
+const int ACES = 1;
+const int UNCHARTED2 = 2;
+
+uniform sampler2D source_tex;
+// 1x1 texture holding the adapted average luminance, see adapt.glsl
+uniform sampler2D adapted_tex;
+
+uniform float exposure;
+uniform bool auto_exposure;
+uniform float key;
+uniform float compensation;
+uniform float min_exposure;
+uniform float max_exposure;
+
+uniform int tonemap_operator;
+uniform float white_point;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+vec3 reinhard(vec3 hdr) {
+    return hdr / (1.0 + hdr);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 hdr) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((hdr * (a * hdr + b)) / (hdr * (c * hdr + d) + e), 0.0, 1.0);
+}
+
+// John Hable's filmic curve from Uncharted 2
+vec3 uncharted2_curve(vec3 x) {
+    const float A = 0.15;
+    const float B = 0.50;
+    const float C = 0.10;
+    const float D = 0.20;
+    const float E = 0.02;
+    const float F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+vec3 uncharted2(vec3 hdr) {
+    return uncharted2_curve(hdr * 2.0) / uncharted2_curve(vec3(white_point));
+}
+
+void main() {
+    float scale = exposure;
+    if (auto_exposure) {
+        float average = texture(adapted_tex, vec2(0.5)).r;
+        scale = clamp(key / max(average, 0.0001), min_exposure, max_exposure);
+    }
+    scale *= exp2(compensation);
+
+    vec3 hdr = max(texture(source_tex, f_tex).rgb, 0.0) * scale;
+    vec3 ldr;
+    if (tonemap_operator == ACES) {
+        ldr = aces(hdr);
+    } else if (tonemap_operator == UNCHARTED2) {
+        ldr = uncharted2(hdr);
+    } else {
+        ldr = reinhard(hdr);
+    }
+    colour = vec4(ldr, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 440
+
+uniform sampler2D source_tex;
+uniform float strength;
+uniform float radius;
+uniform float softness;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    vec3 rgb = texture(source_tex, f_tex).rgb;
+    float distance_from_centre = length(f_tex - 0.5);
+    float falloff = smoothstep(radius, radius - softness, distance_from_centre);
+    colour = vec4(rgb * mix(1.0, falloff, strength), 1.0);
+}
@@ -1,10 +1,16 @@
 #version 440
 
+#include "common.glsl"
+
 uniform sampler2D normal_map;
 uniform sampler2D depth_map;
 uniform sampler2D diffuse_map;
+// With METALLIC_ROUGHNESS this holds ambient occlusion, roughness and metallic in its red,
+// green and blue channels, scaled by the object's factors
 uniform sampler2D specular_map;
 uniform float depth_scale;
+uniform float roughness;
+uniform float metallic;
 
 uniform vec3 eye;
 
@@ -14,7 +20,11 @@ in vec2 f_tex;
 
 out vec4 diffuse;
 out vec4 normal;
+#ifdef METALLIC_ROUGHNESS
+out vec4 material;
+#else
 out vec4 specular;
+#endif
 
 vec2 parallax_mapping(vec2 tex_coords, vec3 view_dir) {
     float depth = texture(depth_map, tex_coords).x;
@@ -23,14 +33,33 @@ vec2 parallax_mapping(vec2 tex_coords, vec3 view_dir) {
 }
 
 void main() {
+#ifdef PARALLAX_MAPPING
     vec3 view_dir = normalize(transpose(tbn) * (eye - f_pos));
     vec2 tex_coords = parallax_mapping(f_tex, view_dir); 
     if(tex_coords.x > 1.0 || tex_coords.y > 1.0 || tex_coords.x < 0.0 || tex_coords.y < 0.0){
         discard;
     }
+#else
+    vec2 tex_coords = f_tex;
+#endif
+
+#ifdef NORMAL_MAPPING
+    vec3 world_normal = normalize(tbn * (texture(normal_map, tex_coords).xyz * 2.0 - 1.0));
+#else
+    vec3 world_normal = normalize(tbn[2]);
+#endif
 
     diffuse = texture(diffuse_map, tex_coords);
-    normal = (normalize(tbn * (texture(normal_map, tex_coords) * 2.0 - 1.0)) + 1.0) * 0.5;
+    normal = vec4(encode_normal(world_normal), 0.0, 1.0);
+#ifdef METALLIC_ROUGHNESS
+    vec3 occlusion_roughness_metallic = texture(specular_map, tex_coords).rgb;
+    material = vec4(
+        occlusion_roughness_metallic.b * metallic,
+        occlusion_roughness_metallic.g * roughness,
+        occlusion_roughness_metallic.r,
+        1.0
+    );
+#else
     specular = texture(specular_map, tex_coords);
-
+#endif
 }
\ No newline at end of file
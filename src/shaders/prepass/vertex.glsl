@@ -19,6 +19,7 @@ void main() {
     vec3 N = normalize(mat3(model) * normal);
     tbn = mat3(T, B, N);
     f_tex = tex_coord;
-    f_pos = model * vec4(position, 1.0);
-    gl_Position = view * f_pos;
+    vec4 world_pos = model * vec4(position, 1.0);
+    f_pos = world_pos.xyz;
+    gl_Position = view * world_pos;
 }
\ No newline at end of file
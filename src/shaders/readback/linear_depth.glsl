@@ -0,0 +1,18 @@
+#version 440
+
+#include "common.glsl"
+
+uniform sampler2D depth_tex;
+
+uniform float T1;
+uniform float T2;
+uniform bool orthographic;
+
+in vec2 f_tex;
+
+out vec4 colour;
+
+void main() {
+    float depth = linear_depth(texture(depth_tex, f_tex).r, T1, T2, orthographic);
+    colour = vec4(depth, depth, depth, 1.0);
+}
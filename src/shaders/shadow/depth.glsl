@@ -0,0 +1,5 @@
+#version 440
+
+// Directional and spot shadow maps only need the depth buffer
+
+void main() {}
@@ -0,0 +1,15 @@
+#version 440
+
+// Point light shadow cube maps store the distance to the light over its radius, so every face
+// can be compared the same way
+
+in vec3 f_pos;
+
+uniform vec3 light_pos;
+uniform float far;
+
+out float distance_to_light;
+
+void main() {
+    distance_to_light = length(f_pos - light_pos) / far;
+}
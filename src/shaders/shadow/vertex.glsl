@@ -0,0 +1,14 @@
+#version 440
+
+uniform mat4 view;
+uniform mat4 model;
+
+in vec3 position;
+
+out vec3 f_pos;
+
+void main() {
+    vec4 world_pos = model * vec4(position, 1.0);
+    f_pos = world_pos.xyz;
+    gl_Position = view * world_pos;
+}
@@ -0,0 +1,95 @@
+#version 440
+
+#include "common.glsl"
+
+// The background where the G-buffer has no geometry. SKY_CUBEMAP or SKY_EQUIRECT picks the map it
+// is sampled from, without either it is the procedural sky. See `SkyPass`
+
+in vec2 f_tex;
+
+uniform sampler2D depth_tex;
+uniform mat4 inv_projection;
+uniform float sky_intensity;
+
+#ifdef SKY_CUBEMAP
+uniform samplerCube sky_cube;
+
+vec3 sky_colour(vec3 direction) {
+    return texture(sky_cube, direction).rgb;
+}
+#else
+#ifdef SKY_EQUIRECT
+uniform sampler2D sky_equirect;
+
+vec3 sky_colour(vec3 direction) {
+    return texture(sky_equirect, equirect_coords(direction)).rgb;
+}
+#else
+// See `ProceduralSky` in sky.rs. Vectors hold the Y, x and y channels
+uniform vec3 sun_direction;
+uniform float sun_size;
+uniform vec3 sun_colour;
+uniform vec3 ground_colour;
+uniform vec3 zenith;
+uniform vec3 perez_a;
+uniform vec3 perez_b;
+uniform vec3 perez_c;
+uniform vec3 perez_d;
+uniform vec3 perez_e;
+
+// How much brighter the sun's disc is than the light it casts
+const float SUN_DISC_SCALE = 10.0;
+
+vec3 perez(float cos_theta, float gamma) {
+    return (1.0 + perez_a * exp(perez_b / cos_theta))
+        * (1.0 + perez_c * exp(perez_d * gamma) + perez_e * cos(gamma) * cos(gamma));
+}
+
+vec3 yxy_to_rgb(vec3 yxy) {
+    float luminance = yxy.x;
+    vec3 xyz = vec3(
+        yxy.y * luminance / yxy.z,
+        luminance,
+        (1.0 - yxy.y - yxy.z) * luminance / yxy.z
+    );
+    return mat3(
+        3.2406, -0.9689, 0.0557,
+        -1.5372, 1.8758, -0.2040,
+        -0.4986, 0.0415, 1.0570
+    ) * xyz;
+}
+
+vec3 sky_colour(vec3 direction) {
+    vec3 sun = normalize(sun_direction);
+    float sun_zenith = acos(clamp(sun.y, -1.0, 1.0));
+    // The horizon is stretched down over the ground so there is no seam
+    float cos_theta = max(direction.y, 0.01);
+    float gamma = acos(clamp(dot(direction, sun), -1.0, 1.0));
+
+    vec3 yxy = zenith * perez(cos_theta, gamma) / perez(1.0, sun_zenith);
+    vec3 sky = max(yxy_to_rgb(yxy), vec3(0.0));
+    if (direction.y < 0.0) {
+        sky = mix(sky, ground_colour, smoothstep(0.0, -0.05, direction.y));
+    } else if (gamma < sun_size) {
+        sky += sun_colour * SUN_DISC_SCALE / sky_intensity;
+    }
+    return sky;
+}
+#endif
+#endif
+
+out vec4 colour;
+
+void main() {
+    if (texture(depth_tex, f_tex).r < 1.0) {
+        discard;
+    }
+
+    // The ray through the pixel, from the near plane to the far plane
+    vec2 ndc = f_tex * 2.0 - 1.0;
+    vec4 near = inv_projection * vec4(ndc, -1.0, 1.0);
+    vec4 far = inv_projection * vec4(ndc, 1.0, 1.0);
+    vec3 direction = normalize(far.xyz / far.w - near.xyz / near.w);
+
+    colour = vec4(sky_colour(direction) * sky_intensity, 1.0);
+}
@@ -0,0 +1,37 @@
+#version 440
+
+#include "common.glsl"
+
+// Blurs away the rotation noise of ssao/occlusion.glsl, weighting neighbours by how close their
+// depth is so occlusion doesn't bleed across edges
+
+in vec2 f_tex;
+
+uniform sampler2D occlusion_tex;
+uniform sampler2D depth_tex;
+uniform float T1;
+uniform float T2;
+uniform int blur_radius;
+uniform float blur_sharpness;
+
+out vec4 colour;
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(occlusion_tex, 0));
+    float centre = linear_depth(texture(depth_tex, f_tex).r, T1, T2, false);
+
+    float total = 0.0;
+    float weights = 0.0;
+    for (int y = -blur_radius; y <= blur_radius; y++) {
+        for (int x = -blur_radius; x <= blur_radius; x++) {
+            vec2 coords = f_tex + vec2(x, y) * texel;
+            float depth = linear_depth(texture(depth_tex, coords).r, T1, T2, false);
+            float difference = (depth - centre) / centre;
+            float weight = exp(-difference * difference * blur_sharpness);
+            total += texture(occlusion_tex, coords).r * weight;
+            weights += weight;
+        }
+    }
+
+    colour = vec4(vec3(total / weights), 1.0);
+}
@@ -0,0 +1,62 @@
+#version 440
+
+#include "common.glsl"
+
+// Ambient occlusion from the G-buffer, see `Ssao`. Everything is in view space, so
+// `inv_projection` is the inverse of the projection alone
+
+in vec2 f_tex;
+
+uniform sampler2D depth_tex;
+uniform sampler2D normal_tex;
+uniform sampler2D noise_tex;
+// Offsets in the hemisphere around +z, scaled to at most 1
+uniform samplerBuffer kernel;
+uniform int sample_count;
+uniform float radius;
+uniform float bias;
+
+uniform float T1;
+uniform float T2;
+uniform mat4 inv_projection;
+uniform mat4 projection;
+uniform mat4 view;
+
+out vec4 colour;
+
+vec3 view_position(vec2 coords) {
+    float depth = texture(depth_tex, coords).r;
+    return reconstruct_position(depth, coords, T1, T2, inv_projection);
+}
+
+void main() {
+    float depth = texture(depth_tex, f_tex).r;
+    if (depth >= 1.0) {
+        colour = vec4(1.0);
+        return;
+    }
+
+    vec3 position = reconstruct_position(depth, f_tex, T1, T2, inv_projection);
+    vec3 normal = normalize(mat3(view) * decode_normal(texture(normal_tex, f_tex).xy));
+
+    // Turns the kernel around the normal by the noise texel's angle
+    vec2 noise_scale = vec2(textureSize(depth_tex, 0)) / vec2(textureSize(noise_tex, 0));
+    vec3 rotation = vec3(texture(noise_tex, f_tex * noise_scale).xy, 0.0);
+    vec3 tangent = normalize(rotation - normal * dot(rotation, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 tbn = mat3(tangent, bitangent, normal);
+
+    float occlusion = 0.0;
+    for (int i = 0; i < sample_count; i++) {
+        vec3 sample_pos = position + tbn * texelFetch(kernel, i).xyz * radius;
+        vec4 clip = projection * vec4(sample_pos, 1.0);
+        vec2 coords = clip.xy / clip.w * 0.5 + 0.5;
+        float scene_z = view_position(coords).z;
+
+        // Geometry far in front of the sample is a different object and doesn't count
+        float range = smoothstep(0.0, 1.0, radius / abs(position.z - scene_z));
+        occlusion += (scene_z >= sample_pos.z + bias ? 1.0 : 0.0) * range;
+    }
+
+    colour = vec4(vec3(1.0 - occlusion / float(sample_count)), 1.0);
+}
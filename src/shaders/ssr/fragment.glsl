@@ -0,0 +1,153 @@
+#version 440
+
+#include "common.glsl"
+#include "lighting/shade.glsl"
+#include "lighting/surface.glsl"
+
+// Screen-space reflections, see `Ssr`. Rays are marched in view space against the depth buffer
+// and pick up the colour of `source_tex` where they hit. SSR_ENVIRONMENT is defined when the
+// environment drawn by `LightingPass::draw_ambient` is given: its reflection is already in
+// `source_tex`, so a hit swaps it for the scene and a miss leaves it as it is. SSAO is defined
+// as well when the ambient pass was occluded, so the same occlusion is removed
+
+in vec2 f_tex;
+
+uniform sampler2D source_tex;
+uniform mat4 view;
+uniform mat4 projection;
+uniform float shininess;
+
+uniform int max_steps;
+uniform int refine_steps;
+uniform float max_distance;
+uniform float thickness;
+uniform float edge_fade;
+uniform float max_roughness;
+uniform float ssr_intensity;
+
+#ifdef SSR_ENVIRONMENT
+uniform samplerCube prefiltered_map;
+uniform sampler2D brdf_lut;
+uniform float prefiltered_lod;
+uniform float ambient_intensity;
+
+#ifdef SSAO
+// From `Ssao`, 1 where nothing is occluded
+uniform sampler2D ao_tex;
+#endif
+#endif
+
+out vec4 colour;
+
+vec2 project(vec3 view_pos) {
+    vec4 clip = projection * vec4(view_pos, 1.0);
+    return clip.xy / clip.w * 0.5 + 0.5;
+}
+
+// How far `view_pos` is behind the depth buffer
+float depth_difference(vec3 view_pos) {
+    float depth = texture(depth_tex, project(view_pos)).r;
+    return -view_pos.z - linear_depth(depth, T1, T2, false);
+}
+
+// Marches from `origin` along `direction`, both in view space. Returns the screen coordinates
+// of the hit and how far along the ray it was in 0..1, or a negative distance for a miss
+vec3 trace(vec3 origin, vec3 direction) {
+    float step_length = max_distance / float(max_steps);
+    vec3 previous = origin;
+    for (int i = 1; i <= max_steps; i++) {
+        vec3 current = origin + direction * step_length * float(i);
+        vec2 coords = project(current);
+        if (current.z >= 0.0 || any(lessThan(coords, vec2(0.0))) || any(greaterThan(coords, vec2(1.0)))) {
+            break;
+        }
+
+        float difference = depth_difference(current);
+        // Further behind than `thickness` the ray passes behind the object instead of hitting it
+        if (difference > 0.0 && difference < thickness) {
+            // Binary search for the crossing between the last two steps
+            vec3 low = previous;
+            vec3 high = current;
+            for (int j = 0; j < refine_steps; j++) {
+                vec3 middle = (low + high) * 0.5;
+                if (depth_difference(middle) > 0.0) {
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+            return vec3(project(high), float(i) / float(max_steps));
+        }
+        previous = current;
+    }
+    return vec3(0.0, 0.0, -1.0);
+}
+
+void main() {
+    vec3 source = texture(source_tex, f_tex).rgb;
+    float depth = texture(depth_tex, f_tex).r;
+    if (depth >= 1.0) {
+        colour = vec4(source, 1.0);
+        return;
+    }
+
+    SurfaceData surface = read_surface(f_tex, depth);
+#ifdef METALLIC_ROUGHNESS
+    float roughness = surface.roughness;
+#else
+    // The roughness whose highlight is about as wide as a Blinn-Phong one of `shininess`
+    float roughness = sqrt(2.0 / (shininess + 2.0));
+#endif
+    if (roughness >= max_roughness) {
+        colour = vec4(source, 1.0);
+        return;
+    }
+
+    vec3 reflected = reflect(-surface.view_dir, surface.normal);
+    float n_dot_v = max(dot(surface.normal, surface.view_dir), 0.0);
+
+    // How much of the reflection the surface shows, as in lighting/ambient.glsl
+#ifdef METALLIC_ROUGHNESS
+    vec3 fresnel = fresnel_schlick_roughness(n_dot_v, base_reflectance(surface), roughness);
+#ifdef SSR_ENVIRONMENT
+    vec2 brdf = texture(brdf_lut, vec2(n_dot_v, roughness)).rg;
+    vec3 weight = (fresnel * brdf.x + brdf.y) * surface.ao;
+#else
+    vec3 weight = fresnel * surface.ao;
+#endif
+#else
+    vec3 weight = surface.specular;
+#endif
+
+    vec3 origin = (view * vec4(surface.position, 1.0)).xyz;
+    vec3 direction = normalize(mat3(view) * reflected);
+    vec3 hit = trace(origin, direction);
+
+    float confidence = 0.0;
+    if (hit.z >= 0.0) {
+        vec2 edges = vec2(1.0);
+        if (edge_fade > 0.0) {
+            edges = smoothstep(vec2(0.0), vec2(edge_fade), hit.xy)
+                * smoothstep(vec2(0.0), vec2(edge_fade), 1.0 - hit.xy);
+        }
+        confidence = edges.x * edges.y
+            // Hits near the end of the ray would pop in and out as it moves
+            * (1.0 - smoothstep(0.5, 1.0, hit.z))
+            * (1.0 - smoothstep(max_roughness * 0.5, max_roughness, roughness))
+            // Rays towards the camera mostly hit the back of things, which aren't on screen
+            * (1.0 - smoothstep(0.0, 0.5, direction.z));
+    }
+    vec3 reflection = texture(source_tex, hit.xy).rgb * ssr_intensity;
+
+#ifdef SSR_ENVIRONMENT
+    // Exactly the reflection lighting/ambient.glsl added, which the hit replaces
+    vec3 environment = textureLod(prefiltered_map, reflected, roughness * prefiltered_lod).rgb
+        * ambient_intensity;
+#ifdef SSAO
+    environment *= texture(ao_tex, f_tex).r;
+#endif
+    reflection -= environment;
+#endif
+
+    colour = vec4(max(source + reflection * weight * confidence, vec3(0.0)), 1.0);
+}
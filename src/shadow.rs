@@ -0,0 +1,275 @@
+// Shadows are opt-in twice: only lights given a `ShadowMap` cast them, and only objects with
+// `casts_shadows` set are drawn into it. Directional lights get an orthographic map around the
+// camera's look at point, spot lights a perspective map along their cone and point and area
+// lights a cube map. A shadowed light is drawn with `LightingPass::draw_shadowed_light` and a
+// program compiled with the shadow defines from `lighting_defines`. Lights drawn together with
+// `LightingPass::draw_clustered` can't cast shadows, so shadowed lights have to be left out of
+// the `ClusteredLights` and drawn on their own
+
+use camera::PCamera;
+use glium::backend::glutin::Display;
+use glium::draw_parameters::{Depth, DepthTest, DrawParameters};
+use glium::framebuffer::{DepthRenderBuffer, SimpleFrameBuffer};
+use glium::index::{NoIndices, PrimitiveType::TrianglesList};
+use glium::texture::{
+    Cubemap, DepthFormat, DepthTexture2d, MipmapsOption::NoMipmap, UncompressedFloatFormat,
+};
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction,
+    UniformValue, Uniforms,
+};
+use glium::{Program, Surface};
+use ibl::cube_faces;
+use light::Light;
+use program::shadow_program;
+use render_object::{ModelMatrix, RenderObject};
+use std::error::Error;
+use std::f32::consts::PI;
+use {Mat4, Pnt3, Vec3, OV, PV};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    // Width and height of the map, or of each cube map face
+    pub resolution: u32,
+    // Subtracted from the surface depth before comparing, in the map's 0..1 depth units
+    pub bias: f32,
+    // World distance the surface is moved along its normal before looking it up
+    pub normal_bias: f32,
+    // Texels on each side of the lookup that are filtered, 0 for hard shadows
+    pub pcf_radius: u32,
+    // Near plane of spot and point light maps
+    pub near: f32,
+    // Half the width of the area a directional light's map covers
+    pub directional_extent: f32,
+    // Depth of the area a directional light's map covers, centred on the look at point
+    pub directional_depth: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings {
+            resolution: 1024,
+            bias: 0.002,
+            normal_bias: 0.02,
+            pcf_radius: 1,
+            near: 0.05,
+            directional_extent: 20.0,
+            directional_depth: 100.0,
+        }
+    }
+}
+
+enum ShadowTarget {
+    Map(DepthTexture2d),
+    // Distance to the light over its radius, with a depth buffer for drawing it
+    Cube(Cubemap, DepthRenderBuffer),
+}
+
+pub struct ShadowMap {
+    // Changing the resolution recreates the map on the next `render`
+    pub settings: ShadowSettings,
+    target: Option<ShadowTarget>,
+    depth_program: Program,
+    distance_program: Program,
+    // View projection of the light for 2d maps
+    matrix: Mat4,
+    // Position and range of the light for cube maps
+    position: Vec3,
+    far: f32,
+}
+
+impl ShadowMap {
+    pub fn new(display: &Display, settings: ShadowSettings) -> Result<ShadowMap, Box<Error>> {
+        Ok(ShadowMap {
+            settings,
+            target: None,
+            depth_program: shadow_program(display, false)?,
+            distance_program: shadow_program(display, true)?,
+            matrix: Mat4::identity(),
+            position: Vec3::new(0.0, 0.0, 0.0),
+            far: 1.0,
+        })
+    }
+
+    // Whether the last `render` made a cube map, see `lighting_defines`
+    pub fn is_cube(&self) -> bool {
+        match self.target {
+            Some(ShadowTarget::Cube(..)) => true,
+            _ => false,
+        }
+    }
+
+    // Draws the shadow casting objects from `light`. `camera` places a directional light's map
+    pub fn render<T: ModelMatrix>(
+        &mut self,
+        display: &Display,
+        light: &Light,
+        objects: &[RenderObject<T>],
+        camera: &PCamera,
+    ) -> Result<(), Box<Error>> {
+        let cube = match light {
+            Light::Point { .. } | Light::Area { .. } => true,
+            Light::Directional { .. } | Light::Spot { .. } => false,
+        };
+        self.create_target(display, cube)?;
+
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let casters: Vec<&RenderObject<T>> =
+            objects.iter().filter(|object| object.casts_shadows).collect();
+
+        match (light, self.target.as_ref().expect("the target was just created")) {
+            (Light::Directional { direction, .. }, ShadowTarget::Map(texture)) => {
+                let settings = self.settings;
+                let centre = camera.look_at.coords;
+                let eye = centre - direction * settings.directional_depth * 0.5;
+                let extent = settings.directional_extent;
+                let projection =
+                    OV::new(-extent, extent, -extent, extent, 0.0, settings.directional_depth);
+                self.matrix = projection.as_matrix() * look_along(&eye, direction);
+
+                let mut framebuffer = SimpleFrameBuffer::depth_only(display, texture)?;
+                framebuffer.clear_depth(1.0);
+                let program = &self.depth_program;
+                draw_casters(&mut framebuffer, &casters, &self.matrix, program, self, &draw_parameters)?;
+            }
+            (
+                Light::Spot {
+                    position,
+                    direction,
+                    radius,
+                    outer_angle,
+                    ..
+                },
+                ShadowTarget::Map(texture),
+            ) => {
+                let projection = PV::new(1.0, outer_angle * 2.0, self.settings.near, *radius);
+                self.matrix = projection.as_matrix() * look_along(position, direction);
+
+                let mut framebuffer = SimpleFrameBuffer::depth_only(display, texture)?;
+                framebuffer.clear_depth(1.0);
+                let program = &self.depth_program;
+                draw_casters(&mut framebuffer, &casters, &self.matrix, program, self, &draw_parameters)?;
+            }
+            (_, ShadowTarget::Cube(cubemap, depth)) => {
+                self.position = light.position().expect("point and area lights have a position");
+                self.far = light.radius().expect("point and area lights have a radius");
+                let projection = *PV::new(1.0, PI / 2.0, self.settings.near, self.far).as_matrix();
+
+                for &(layer, direction, _, up) in cube_faces().iter() {
+                    let view = projection * look_at(&self.position, &direction, &up);
+                    let face = cubemap.main_level().image(layer);
+                    let mut framebuffer = SimpleFrameBuffer::with_depth_buffer(display, face, depth)?;
+                    // Empty directions are as far away as possible
+                    framebuffer.clear_color_and_depth((1.0, 1.0, 1.0, 1.0), 1.0);
+                    let program = &self.distance_program;
+                    draw_casters(&mut framebuffer, &casters, &view, program, self, &draw_parameters)?;
+                }
+            }
+            _ => unreachable!("the target matches the light"),
+        }
+        Ok(())
+    }
+
+    fn create_target(&mut self, display: &Display, cube: bool) -> Result<(), Box<Error>> {
+        let resolution = self.settings.resolution;
+        let matches = match self.target {
+            Some(ShadowTarget::Map(ref texture)) => !cube && texture.width() == resolution,
+            Some(ShadowTarget::Cube(ref cubemap, _)) => cube && cubemap.width() == resolution,
+            None => false,
+        };
+        if matches {
+            return Ok(());
+        }
+
+        self.target = Some(if cube {
+            ShadowTarget::Cube(
+                Cubemap::empty_with_format(
+                    display,
+                    UncompressedFloatFormat::F32,
+                    NoMipmap,
+                    resolution,
+                )?,
+                DepthRenderBuffer::new(display, DepthFormat::F32, resolution, resolution)?,
+            )
+        } else {
+            ShadowTarget::Map(DepthTexture2d::empty_with_format(
+                display,
+                DepthFormat::F32,
+                NoMipmap,
+                resolution,
+                resolution,
+            )?)
+        });
+        Ok(())
+    }
+}
+
+// Samples the map directly, without filtering between depths
+impl Uniforms for ShadowMap {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        let sampler = SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Nearest,
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            ..Default::default()
+        };
+        match self.target {
+            Some(ShadowTarget::Map(ref texture)) => {
+                output("shadow_map", UniformValue::DepthTexture2d(texture, Some(sampler)));
+                output("shadow_matrix", UniformValue::Mat4(*self.matrix.as_ref()));
+            }
+            Some(ShadowTarget::Cube(ref cubemap, _)) => {
+                output("shadow_cube", UniformValue::Cubemap(cubemap, Some(sampler)));
+                output("shadow_light_pos", UniformValue::Vec3(*self.position.as_ref()));
+                output("shadow_far", UniformValue::Float(self.far));
+            }
+            None => {}
+        }
+        output("shadow_bias", UniformValue::Float(self.settings.bias));
+        output("shadow_normal_bias", UniformValue::Float(self.settings.normal_bias));
+        output("shadow_pcf_radius", UniformValue::SignedInt(self.settings.pcf_radius as i32));
+    }
+}
+
+// A view matrix at `eye` looking along `direction`
+fn look_along(eye: &Vec3, direction: &Vec3) -> Mat4 {
+    let up = if direction.y.abs() > 0.99 { Vec3::z() } else { Vec3::y() };
+    look_at(eye, direction, &up)
+}
+
+fn look_at(eye: &Vec3, direction: &Vec3, up: &Vec3) -> Mat4 {
+    let eye = Pnt3::from_coordinates(*eye);
+    Mat4::look_at_rh(&eye, &(eye + direction), up)
+}
+
+// The light position and range are only read by the cube map program
+fn draw_casters<T: ModelMatrix>(
+    framebuffer: &mut SimpleFrameBuffer,
+    casters: &[&RenderObject<T>],
+    view: &Mat4,
+    program: &Program,
+    shadow_map: &ShadowMap,
+    draw_parameters: &DrawParameters,
+) -> Result<(), Box<Error>> {
+    for object in casters {
+        let uniforms = uniform! {
+            view: *view.as_ref(),
+            model: object.model_matrix.matrix(),
+            light_pos: *shadow_map.position.as_ref(),
+            far: shadow_map.far,
+        };
+        framebuffer.draw(object.buffer, NoIndices(TrianglesList), program, &uniforms, draw_parameters)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+// The background drawn wherever the G-buffer has no geometry, see `LightingPass::draw_sky`
+
+use glium::backend::glutin::Display;
+use glium::texture::{Cubemap, Texture2d};
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction,
+    UniformValue, Uniforms,
+};
+use glium::Program;
+use light::Light;
+use preprocess::{flags, Defines};
+use program::fullscreen_program;
+use std::error::Error;
+use std::f32::consts::PI;
+use Vec3;
+
+// The Preetham analytic daylight model. Luminances are in kcd/m^2, so they need scaling down
+// with `SkyPass::intensity` or the camera exposure
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProceduralSky {
+    // Towards the sun, not necessarily normalized
+    pub sun_direction: Vec3,
+    // Haziness of the atmosphere, from 2 for a clear sky to about 10
+    pub turbidity: f32,
+    // Multiplies the sun's colour in `sun_light`
+    pub sun_intensity: f32,
+    // Angular radius of the sun's disc in radians, 0 to hide it
+    pub sun_size: f32,
+    // Below the horizon, in the same units as the sky
+    pub ground_colour: [f32; 3],
+}
+
+impl ProceduralSky {
+    pub fn new(sun_direction: Vec3) -> ProceduralSky {
+        ProceduralSky {
+            sun_direction,
+            turbidity: 2.5,
+            sun_intensity: 5.0,
+            sun_size: 0.0047,
+            ground_colour: [0.4, 0.4, 0.4],
+        }
+    }
+
+    // Angle between the sun and straight up
+    fn sun_zenith(&self) -> f32 {
+        self.sun_direction.normalize().y.max(-1.0).min(1.0).acos()
+    }
+
+    // The A to E coefficients of the Perez distribution for Y, x and y
+    pub fn perez_coefficients(&self) -> [[f32; 3]; 5] {
+        let t = self.turbidity;
+        [
+            [0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608],
+            [-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092],
+            [-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102],
+            [0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537],
+            [-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529],
+        ]
+    }
+
+    // Luminance and chromaticity straight up, as Yxy
+    pub fn zenith(&self) -> [f32; 3] {
+        let t = self.turbidity;
+        // The fit is only valid for the sun above the horizon
+        let theta = self.sun_zenith().min(PI / 2.0);
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let thetas = [theta * theta * theta, theta * theta, theta, 1.0];
+        let turbidities = [t * t, t, 1.0];
+        let chromaticity = |m: [[f32; 4]; 3]| -> f32 {
+            (0..3)
+                .map(|i| turbidities[i] * (0..4).map(|j| m[i][j] * thetas[j]).sum::<f32>())
+                .sum()
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        [luminance.max(0.0), x, y]
+    }
+
+    // Fraction of each of red, green and blue sunlight that makes it through the atmosphere,
+    // from Rayleigh and aerosol scattering
+    pub fn sun_transmittance(&self) -> [f32; 3] {
+        let theta = self.sun_zenith();
+        if theta >= PI / 2.0 {
+            return [0.0, 0.0, 0.0];
+        }
+        // Relative optical mass of the air the light passes through
+        let mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        let mut transmittance = [0.0; 3];
+        // Wavelengths in micrometres
+        for (value, &lambda) in transmittance.iter_mut().zip(&[0.65f32, 0.57, 0.475]) {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            *value = rayleigh * aerosol;
+        }
+        transmittance
+    }
+
+    // A directional light matching the sun, to light the scene under this sky
+    pub fn sun_light(&self) -> Light {
+        let transmittance = self.sun_transmittance();
+        Light::directional(
+            -self.sun_direction,
+            [
+                transmittance[0] * self.sun_intensity,
+                transmittance[1] * self.sun_intensity,
+                transmittance[2] * self.sun_intensity,
+            ],
+        )
+    }
+}
+
+pub enum Sky {
+    Cubemap(Cubemap),
+    // An equirectangular panorama, e.g. from `ibl::equirect_texture`
+    Equirect(Texture2d),
+    Procedural(ProceduralSky),
+}
+
+pub struct SkyPass {
+    pub sky: Sky,
+    // Scales the sky's colour
+    pub intensity: f32,
+    cubemap_program: Program,
+    equirect_program: Program,
+    procedural_program: Program,
+}
+
+impl SkyPass {
+    pub fn new(display: &Display, sky: Sky) -> Result<SkyPass, Box<Error>> {
+        let intensity = match sky {
+            Sky::Procedural(_) => 0.05,
+            _ => 1.0,
+        };
+        Ok(SkyPass {
+            sky,
+            intensity,
+            cubemap_program: fullscreen_program(display, "sky/fragment.glsl", &flags(&["SKY_CUBEMAP"]))?,
+            equirect_program: fullscreen_program(display, "sky/fragment.glsl", &flags(&["SKY_EQUIRECT"]))?,
+            procedural_program: fullscreen_program(display, "sky/fragment.glsl", &Defines::new())?,
+        })
+    }
+
+    // The program for the current `sky`
+    pub(crate) fn program(&self) -> &Program {
+        match self.sky {
+            Sky::Cubemap(_) => &self.cubemap_program,
+            Sky::Equirect(_) => &self.equirect_program,
+            Sky::Procedural(_) => &self.procedural_program,
+        }
+    }
+}
+
+impl Uniforms for SkyPass {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        output("sky_intensity", UniformValue::Float(self.intensity));
+        match self.sky {
+            Sky::Cubemap(ref cubemap) => {
+                let sampler = SamplerBehavior {
+                    minify_filter: MinifySamplerFilter::Linear,
+                    magnify_filter: MagnifySamplerFilter::Linear,
+                    ..Default::default()
+                };
+                output("sky_cube", UniformValue::Cubemap(cubemap, Some(sampler)));
+            }
+            Sky::Equirect(ref texture) => {
+                let sampler = SamplerBehavior {
+                    wrap_function: (
+                        SamplerWrapFunction::Repeat,
+                        SamplerWrapFunction::Clamp,
+                        SamplerWrapFunction::Clamp,
+                    ),
+                    minify_filter: MinifySamplerFilter::Linear,
+                    magnify_filter: MagnifySamplerFilter::Linear,
+                    ..Default::default()
+                };
+                output("sky_equirect", UniformValue::Texture2d(texture, Some(sampler)));
+            }
+            Sky::Procedural(ref sky) => {
+                let perez = sky.perez_coefficients();
+                let sun = sky.sun_direction.normalize();
+                output("sun_direction", UniformValue::Vec3(*sun.as_ref()));
+                output("sun_size", UniformValue::Float(sky.sun_size));
+                output("sun_colour", UniformValue::Vec3(sky.sun_light().colour()));
+                output("ground_colour", UniformValue::Vec3(sky.ground_colour));
+                output("zenith", UniformValue::Vec3(sky.zenith()));
+                output("perez_a", UniformValue::Vec3(perez[0]));
+                output("perez_b", UniformValue::Vec3(perez[1]));
+                output("perez_c", UniformValue::Vec3(perez[2]));
+                output("perez_d", UniformValue::Vec3(perez[3]));
+                output("perez_e", UniformValue::Vec3(perez[4]));
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// Screen-space ambient occlusion. Each pixel's view space position is rebuilt from the G-buffer
+// depth and tested against a hemisphere of samples around its normal, rotated per pixel by a
+// small tiling noise texture. The noise is then removed with a blur that doesn't cross depth
+// edges. The result darkens `LightingPass::draw_ambient` when its program is compiled with
+// occlusion, see `ambient_program`
+
+use camera::PCamera;
+use gbuffer::GBuffer;
+use glium::backend::glutin::Display;
+use glium::backend::{Context, Facade};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::buffer_texture::{BufferTexture, BufferTextureType};
+use glium::texture::{
+    ClientFormat, MipmapsOption::NoMipmap, RawImage2d, Texture2d, UncompressedFloatFormat,
+};
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction,
+    UniformValue, Uniforms,
+};
+use glium::Program;
+use material::UniformsChain;
+use post::draw_fullscreen;
+use preprocess::Defines;
+use program::fullscreen_program;
+use std::borrow::Cow;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+// Largest `samples` the kernel is built with
+pub const MAX_SSAO_SAMPLES: u32 = 64;
+
+// Width and height of the rotation noise, which the blur has to cover
+const NOISE_SIZE: u32 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SsaoSettings {
+    // World distance around a surface that can occlude it
+    pub radius: f32,
+    // Depth difference ignored when testing samples, against self occlusion on flat surfaces
+    pub bias: f32,
+    // Samples per pixel, up to `MAX_SSAO_SAMPLES`
+    pub samples: u32,
+    // Pixels on each side of the blur, 0 to leave the noise in
+    pub blur_radius: u32,
+    // How quickly the blur stops at depth edges, relative to the pixel's depth
+    pub blur_sharpness: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> SsaoSettings {
+        SsaoSettings {
+            radius: 0.5,
+            bias: 0.025,
+            samples: 16,
+            blur_radius: 2,
+            blur_sharpness: 1000.0,
+        }
+    }
+}
+
+pub struct Ssao {
+    // Changing `samples` rebuilds the kernel on the next `render`
+    pub settings: SsaoSettings,
+    context: Rc<Context>,
+    program: Program,
+    blur_program: Program,
+    kernel: BufferTexture<(f32, f32, f32, f32)>,
+    kernel_len: u32,
+    noise: Texture2d,
+    // The noisy occlusion and the blurred result
+    targets: Option<[Texture2d; 2]>,
+}
+
+impl Ssao {
+    pub fn new(display: &Display, settings: SsaoSettings) -> Result<Ssao, Box<Error>> {
+        let kernel_len = settings.samples.max(1).min(MAX_SSAO_SAMPLES);
+        let noise = RawImage2d {
+            data: Cow::Owned(noise()),
+            width: NOISE_SIZE,
+            height: NOISE_SIZE,
+            format: ClientFormat::F32F32,
+        };
+        Ok(Ssao {
+            settings,
+            context: display.get_context().clone(),
+            program: fullscreen_program(display, "ssao/occlusion.glsl", &Defines::new())?,
+            blur_program: fullscreen_program(display, "ssao/blur.glsl", &Defines::new())?,
+            kernel: BufferTexture::new(display, &kernel(kernel_len), BufferTextureType::Float)?,
+            kernel_len,
+            noise: Texture2d::with_format(display, noise, UncompressedFloatFormat::F32F32, NoMipmap)?,
+            targets: None,
+        })
+    }
+
+    // The blurred occlusion of the last `render`, 1 where nothing is occluded
+    pub fn texture(&self) -> Option<&Texture2d> {
+        self.targets.as_ref().map(|targets| &targets[1])
+    }
+
+    // Computes the occlusion of the G-buffer's contents as seen from `camera`, which has to use
+    // a perspective projection
+    pub fn render(&mut self, gbuffer: &GBuffer, camera: &PCamera) -> Result<(), Box<Error>> {
+        let samples = self.settings.samples.max(1).min(MAX_SSAO_SAMPLES);
+        if samples != self.kernel_len {
+            self.kernel = BufferTexture::new(&self.context, &kernel(samples), BufferTextureType::Float)?;
+            self.kernel_len = samples;
+        }
+        self.resize_targets(gbuffer.light.dimensions())?;
+        let targets = self.targets.as_ref().expect("targets were just created");
+
+        let perspective_mat = camera.projection_matrix();
+        let uniforms = uniform! {
+            // Rebuilds view space rather than world space positions
+            inv_projection: *camera.projection.inverse_as_matrix().as_ref(),
+            projection: *perspective_mat.as_ref(),
+            view: *camera.look_at_matrix().as_ref(),
+            T1: perspective_mat[(2, 2)],
+            T2: perspective_mat[(2, 3)],
+            kernel: &self.kernel,
+            sample_count: samples as i32,
+            radius: self.settings.radius,
+            bias: self.settings.bias,
+            noise_tex: self.noise
+                .sampled()
+                .wrap_function(SamplerWrapFunction::Repeat)
+                .minify_filter(MinifySamplerFilter::Nearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest),
+        };
+        let mut framebuffer = SimpleFrameBuffer::new(&self.context, &targets[0])?;
+        draw_fullscreen(&mut framebuffer, &self.program, &UniformsChain(&uniforms, &gbuffer.textures()))?;
+
+        let uniforms = uniform! {
+            occlusion_tex: targets[0]
+                .sampled()
+                .wrap_function(SamplerWrapFunction::Clamp)
+                .minify_filter(MinifySamplerFilter::Nearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest),
+            T1: perspective_mat[(2, 2)],
+            T2: perspective_mat[(2, 3)],
+            blur_radius: self.settings.blur_radius as i32,
+            blur_sharpness: self.settings.blur_sharpness,
+        };
+        let mut framebuffer = SimpleFrameBuffer::new(&self.context, &targets[1])?;
+        draw_fullscreen(&mut framebuffer, &self.blur_program, &UniformsChain(&uniforms, &gbuffer.textures()))
+    }
+
+    fn resize_targets(&mut self, dimensions: (u32, u32)) -> Result<(), Box<Error>> {
+        if let Some(targets) = &self.targets {
+            if targets[0].dimensions() == dimensions {
+                return Ok(());
+            }
+        }
+
+        let context = &self.context;
+        let target = || {
+            Texture2d::empty_with_format(
+                context,
+                UncompressedFloatFormat::U8,
+                NoMipmap,
+                dimensions.0,
+                dimensions.1,
+            )
+        };
+        self.targets = Some([target()?, target()?]);
+        Ok(())
+    }
+}
+
+// The blurred occlusion as `ao_tex`
+impl Uniforms for Ssao {
+    fn visit_values<'b, F: FnMut(&str, UniformValue<'b>)>(&'b self, mut output: F) {
+        if let Some(texture) = self.texture() {
+            let sampler = SamplerBehavior {
+                minify_filter: MinifySamplerFilter::Nearest,
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                ..Default::default()
+            };
+            output("ao_tex", UniformValue::Texture2d(texture, Some(sampler)));
+        }
+    }
+}
+
+// Point `i` of the van der Corput sequence in `base`
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut digit = 1.0 / base as f32;
+    while i > 0 {
+        result += (i % base) as f32 * digit;
+        i /= base;
+        digit /= base as f32;
+    }
+    result
+}
+
+// Offsets in the hemisphere around +z, more of them close to the centre where occluders
+// matter most. Deterministic so the noise doesn't change between runs
+fn kernel(samples: u32) -> Vec<(f32, f32, f32, f32)> {
+    (0..samples)
+        .map(|i| {
+            let phi = 2.0 * PI * radical_inverse(i + 1, 2);
+            let cos_theta = 1.0 - radical_inverse(i + 1, 3);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let t = (i as f32 + 0.5) / samples as f32;
+            let length = 0.1 + 0.9 * t * t;
+            (
+                phi.cos() * sin_theta * length,
+                phi.sin() * sin_theta * length,
+                cos_theta * length,
+                0.0,
+            )
+        })
+        .collect()
+}
+
+// Unit vectors in the xy plane the kernel is rotated around the normal by, ordered like a Bayer
+// matrix so neighbouring pixels differ as much as possible
+fn noise() -> Vec<f32> {
+    let order = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+    let mut data = Vec::with_capacity(order.len() * 2);
+    for &index in order.iter() {
+        let angle = 2.0 * PI * (index as f32 + 0.5) / order.len() as f32;
+        data.push(angle.cos());
+        data.push(angle.sin());
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_stays_in_the_unit_hemisphere() {
+        for samples in &[1, 16, 64] {
+            let kernel = kernel(*samples);
+            assert_eq!(kernel.len(), *samples as usize);
+            for &(x, y, z, w) in &kernel {
+                let length = (x * x + y * y + z * z).sqrt();
+                assert!(z >= 0.0, "({}, {}, {}) is below the surface", x, y, z);
+                assert!(length <= 1.0 + 1e-6, "({}, {}, {}) is too long", x, y, z);
+                assert_eq!(w, 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_unit_vectors_in_the_xy_plane() {
+        let noise = noise();
+        assert_eq!(noise.len(), 16 * 2);
+        for xy in noise.chunks(2) {
+            let length = (xy[0] * xy[0] + xy[1] * xy[1]).sqrt();
+            assert!((length - 1.0).abs() < 1e-5, "{:?} has length {}", xy, length);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// Screen-space reflections. Rays are marched from each pixel against the G-buffer depth and
+// reflect whatever lit colour they hit, faded out towards the screen edges where the scene
+// they need is missing. Misses fall back to the environment map of `LightingPass::draw_ambient`.
+// Runs as a `PostPass` on the HDR light buffer, so it goes before `Tonemap`
+
+use camera::PCamera;
+use gbuffer::GBuffer;
+use glium::backend::glutin::Display;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::Texture2d;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+use glium::{Program, Surface};
+use ibl::Environment;
+use material::{MaterialModel, UniformsChain};
+use post::{draw_fullscreen, PostInputs, PostPass};
+use preprocess::flags;
+use program::fullscreen_program;
+use ssao::Ssao;
+use std::any::Any;
+use std::error::Error;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SsrSettings {
+    // Steps along each ray, each `max_distance / max_steps` long
+    pub max_steps: u32,
+    // Binary search steps that refine a hit between the last two steps
+    pub refine_steps: u32,
+    // Length of the rays in world units
+    pub max_distance: f32,
+    // How far behind the depth buffer a ray still counts as hitting it
+    pub thickness: f32,
+    // Width of the fade at the screen edges, in 0..1 screen coordinates
+    pub edge_fade: f32,
+    // Surfaces this rough or rougher get no reflections, the ambient pass already blurs them
+    pub max_roughness: f32,
+    pub intensity: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> SsrSettings {
+        SsrSettings {
+            max_steps: 64,
+            refine_steps: 8,
+            max_distance: 20.0,
+            thickness: 0.5,
+            edge_fade: 0.1,
+            max_roughness: 0.6,
+            intensity: 1.0,
+        }
+    }
+}
+
+pub struct Ssr {
+    pub settings: SsrSettings,
+    // The environment drawn by `LightingPass::draw_ambient`, if any. Its reflection is already in
+    // the light buffer, so hits replace it and misses keep it. Without one, hits are added on top
+    pub environment: Option<Rc<Environment>>,
+    // The shininess the lighting pass was drawn with, for Blinn-Phong G-buffers
+    pub shininess: f32,
+    program: Program,
+    environment_program: Program,
+    // For an environment drawn with occlusion, which has to be removed with it
+    occluded_environment_program: Program,
+}
+
+impl Ssr {
+    // `model` is the material model of the G-buffers it will read
+    pub fn new(
+        display: &Display,
+        model: MaterialModel,
+        settings: SsrSettings,
+    ) -> Result<Ssr, Box<Error>> {
+        let mut environment_defines = model.defines();
+        environment_defines.extend(flags(&["SSR_ENVIRONMENT"]));
+        let mut occluded_defines = environment_defines.clone();
+        occluded_defines.extend(flags(&["SSAO"]));
+        Ok(Ssr {
+            settings,
+            environment: None,
+            shininess: 32.0,
+            program: fullscreen_program(display, "ssr/fragment.glsl", &model.defines())?,
+            environment_program: fullscreen_program(display, "ssr/fragment.glsl", &environment_defines)?,
+            occluded_environment_program: fullscreen_program(display, "ssr/fragment.glsl", &occluded_defines)?,
+        })
+    }
+
+    // Draws `source`, the lit image of `gbuffer`, with reflections into `surface`. `occlusion`
+    // is the one `LightingPass::draw_ambient` was given
+    pub fn draw<S: Surface>(
+        &self,
+        surface: &mut S,
+        source: &Texture2d,
+        gbuffer: &GBuffer,
+        occlusion: Option<&Ssao>,
+        camera: &PCamera,
+    ) -> Result<(), Box<Error>> {
+        let settings = self.settings;
+        let perspective_mat = camera.projection_matrix();
+        let uniforms = uniform! {
+            eye: *camera.position.coords.as_ref(),
+            inv_projection: *camera.inv_view_matrix().as_ref(),
+            T1: perspective_mat[(2, 2)],
+            T2: perspective_mat[(2, 3)],
+            view: *camera.look_at_matrix().as_ref(),
+            projection: *perspective_mat.as_ref(),
+            shininess: self.shininess,
+            max_steps: settings.max_steps.max(1) as i32,
+            refine_steps: settings.refine_steps as i32,
+            max_distance: settings.max_distance,
+            thickness: settings.thickness,
+            edge_fade: settings.edge_fade,
+            max_roughness: settings.max_roughness,
+            ssr_intensity: settings.intensity,
+            source_tex: source
+                .sampled()
+                .wrap_function(SamplerWrapFunction::Clamp)
+                .minify_filter(MinifySamplerFilter::Linear)
+                .magnify_filter(MagnifySamplerFilter::Linear),
+        };
+        let uniforms = UniformsChain(&uniforms, &gbuffer.textures());
+
+        match (&self.environment, occlusion) {
+            (Some(environment), Some(ssao)) => draw_fullscreen(
+                surface,
+                &self.occluded_environment_program,
+                &UniformsChain(&UniformsChain(&uniforms, &**environment), ssao),
+            ),
+            (Some(environment), None) => draw_fullscreen(
+                surface,
+                &self.environment_program,
+                &UniformsChain(&uniforms, &**environment),
+            ),
+            (None, _) => draw_fullscreen(surface, &self.program, &uniforms),
+        }
+    }
+}
+
+impl PostPass for Ssr {
+    fn name(&self) -> &str {
+        "ssr"
+    }
+
+    fn apply(
+        &mut self,
+        target: &mut SimpleFrameBuffer,
+        inputs: &PostInputs,
+    ) -> Result<(), Box<Error>> {
+        self.draw(target, inputs.source, inputs.gbuffer, inputs.occlusion, inputs.camera)
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
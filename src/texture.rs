@@ -0,0 +1,250 @@
+use glium::backend::Facade;
+use glium::Rect;
+use glium::texture::{
+    ClientFormat, MipmapsOption, RawImage2d, Texture2d, TextureCreationError,
+    UncompressedFloatFormat::{U16U16U16U16, U8U8U8U8},
+};
+use image::{self, ImageError};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// What a texture is used for, which decides its colour space. Colour maps don't get an sRGB
+// texture format: their sRGB texels are converted on the CPU and uploaded as a linear U16 texture,
+// so shaders sample linear values either way
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureKind {
+    Diffuse,
+    Specular,
+    Normal,
+    Depth,
+}
+
+impl TextureKind {
+    // Whether the file stores sRGB colours, which `load_texture` converts to linear
+    pub fn is_srgb(&self) -> bool {
+        match self {
+            TextureKind::Diffuse => true,
+            TextureKind::Specular | TextureKind::Normal | TextureKind::Depth => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureOptions {
+    pub kind: TextureKind,
+    // Image files store the top row first while OpenGL expects the bottom row first
+    pub flip_vertical: bool,
+    pub mipmaps: bool,
+}
+
+impl TextureOptions {
+    pub fn new(kind: TextureKind) -> TextureOptions {
+        TextureOptions {
+            kind,
+            flip_vertical: true,
+            mipmaps: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TextureErrorCause {
+    Image(ImageError),
+    Creation(TextureCreationError),
+}
+
+#[derive(Debug)]
+pub struct TextureError {
+    pub path: PathBuf,
+    pub cause: TextureErrorCause,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.cause {
+            TextureErrorCause::Image(err) => {
+                write!(f, "failed to load texture {}: {}", self.path.display(), err)
+            }
+            TextureErrorCause::Creation(err) => {
+                write!(f, "failed to create texture {}: {:?}", self.path.display(), err)
+            }
+        }
+    }
+}
+
+impl Error for TextureError {
+    fn description(&self) -> &str {
+        match self.cause {
+            TextureErrorCause::Image(_) => "failed to load texture",
+            TextureErrorCause::Creation(_) => "failed to create texture",
+        }
+    }
+}
+
+// Loads a png, jpeg, tga or bmp file into a texture.
+//
+// glium only exposes sRGB formats through `SrgbTexture2d`, which `RenderObject` can't hold, so
+// colour maps are converted from sRGB to linear while loading and stored with 16 bits per
+// channel to avoid banding in the darks. Normal, depth and specular maps are already linear and
+// are uploaded untouched as 8 bit channels
+pub fn load_texture<F: Facade, P: AsRef<Path>>(
+    facade: &F,
+    path: P,
+    options: TextureOptions,
+) -> Result<Texture2d, TextureError> {
+    let path = path.as_ref();
+    create(facade, path, decode(path, options)?, options)
+}
+
+// Re-reads the file into an existing texture, keeping the texture (and every handle to it)
+// alive. Mipmaps are regenerated by glium when the main level is written. A texture can't change
+// size, so if the file did a new texture is created and returned instead
+pub fn reload_texture<F: Facade, P: AsRef<Path>>(
+    facade: &F,
+    texture: &Texture2d,
+    path: P,
+    options: TextureOptions,
+) -> Result<Option<Texture2d>, TextureError> {
+    let path = path.as_ref();
+    let decoded = decode(path, options)?;
+    let dimensions = decoded.dimensions();
+    if dimensions != texture.dimensions() {
+        return create(facade, path, decoded, options).map(Some);
+    }
+
+    let rect = Rect {
+        left: 0,
+        bottom: 0,
+        width: dimensions.0,
+        height: dimensions.1,
+    };
+    match decoded {
+        Decoded::Srgb(raw) => texture.write(rect, raw),
+        Decoded::Linear(raw) => texture.write(rect, raw),
+    }
+    Ok(None)
+}
+
+fn create<F: Facade>(
+    facade: &F,
+    path: &Path,
+    decoded: Decoded,
+    options: TextureOptions,
+) -> Result<Texture2d, TextureError> {
+    let mipmaps = if options.mipmaps {
+        MipmapsOption::AutoGeneratedMipmaps
+    } else {
+        MipmapsOption::NoMipmap
+    };
+
+    let texture = match decoded {
+        Decoded::Srgb(raw) => Texture2d::with_format(facade, raw, U16U16U16U16, mipmaps),
+        Decoded::Linear(raw) => Texture2d::with_format(facade, raw, U8U8U8U8, mipmaps),
+    };
+
+    texture.map_err(|err| TextureError {
+        path: path.to_path_buf(),
+        cause: TextureErrorCause::Creation(err),
+    })
+}
+
+enum Decoded<'a> {
+    Srgb(RawImage2d<'a, u16>),
+    Linear(RawImage2d<'a, u8>),
+}
+
+impl<'a> Decoded<'a> {
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Decoded::Srgb(raw) => (raw.width, raw.height),
+            Decoded::Linear(raw) => (raw.width, raw.height),
+        }
+    }
+}
+
+fn decode<'a>(path: &Path, options: TextureOptions) -> Result<Decoded<'a>, TextureError> {
+    let image = image::open(path)
+        .map_err(|err| TextureError {
+            path: path.to_path_buf(),
+            cause: TextureErrorCause::Image(err),
+        })?
+        .to_rgba();
+    let dimensions = image.dimensions();
+    let data = image.into_raw();
+
+    if options.kind.is_srgb() {
+        let table = srgb_table();
+        let mut linear = Vec::with_capacity(data.len());
+        for p in data.chunks(4) {
+            linear.push(table[p[0] as usize]);
+            linear.push(table[p[1] as usize]);
+            linear.push(table[p[2] as usize]);
+            linear.push(p[3] as u16 * 257);
+        }
+        let raw = raw_image(linear, dimensions, ClientFormat::U16U16U16U16, options.flip_vertical);
+        Ok(Decoded::Srgb(raw))
+    } else {
+        let raw = raw_image(data, dimensions, ClientFormat::U8U8U8U8, options.flip_vertical);
+        Ok(Decoded::Linear(raw))
+    }
+}
+
+// Builds an rgba raw image, optionally flipping the rows so the first row is the bottom one
+fn raw_image<'a, T: Clone + 'a>(
+    data: Vec<T>,
+    dimensions: (u32, u32),
+    format: ClientFormat,
+    flip_vertical: bool,
+) -> RawImage2d<'a, T> {
+    let data = if flip_vertical {
+        data.chunks(dimensions.0 as usize * 4)
+            .rev()
+            .flat_map(|row| row.iter().cloned())
+            .collect()
+    } else {
+        data
+    };
+
+    RawImage2d {
+        data: Cow::Owned(data),
+        width: dimensions.0,
+        height: dimensions.1,
+        format,
+    }
+}
+
+// The linear 16 bit value of every 8 bit sRGB value
+fn srgb_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        *entry = srgb_to_linear(value as u8);
+    }
+    table
+}
+
+fn srgb_to_linear(value: u8) -> u16 {
+    let c = value as f32 / 255.0;
+    let linear = if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    };
+    (linear * 65535.0).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_table_covers_the_range() {
+        let table = srgb_table();
+        assert_eq!(table[0], 0);
+        assert_eq!(table[255], 65535);
+        // Middle grey, 128, is about 21.6% linear
+        assert!((table[128] as f32 / 65535.0 - 0.2158).abs() < 0.001);
+        assert!(table.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,123 @@
+extern crate glium;
+extern crate renderer;
+
+use glium::glutin::{ContextBuilder, EventsLoop, WindowBuilder};
+use glium::texture::Texture2d;
+use glium::{Display, VertexBuffer};
+use renderer::camera::PCamera;
+use renderer::gbuffer::{FrameBuffers, GBuffer};
+use renderer::light::{Light, LightVolumes};
+use renderer::program::{lighting_program, prepass_program};
+use renderer::render_object::{ModelMatrix, RenderObject};
+use renderer::test::gen_plane;
+use renderer::Vec3;
+use std::io::{self, Write};
+use std::panic;
+
+const SIZE: u32 = 64;
+
+struct Identity;
+
+impl ModelMatrix for Identity {
+    fn matrix(&self) -> [[f32; 4]; 4] {
+        [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+}
+
+// A hidden window's display. The events loop is returned too as it has to outlive the display.
+// None without a display server, in which case the test is skipped
+fn display() -> Option<(EventsLoop, Display)> {
+    let events_loop = panic::catch_unwind(EventsLoop::new).ok()?;
+    let window = WindowBuilder::new().with_visibility(false);
+    let display = Display::new(window, ContextBuilder::new(), &events_loop).ok()?;
+    Some((events_loop, display))
+}
+
+fn texel(display: &Display, value: (f32, f32, f32, f32)) -> Texture2d {
+    Texture2d::new(display, vec![vec![value]]).expect("failed to create texture")
+}
+
+fn assert_close(found: &[f32], expected: &[f32]) {
+    for (found, expected) in found.iter().zip(expected) {
+        assert!(
+            (found - expected).abs() < 0.01,
+            "expected {:?}, found {:?}",
+            expected,
+            found
+        );
+    }
+}
+
+// Draws a white plane facing the camera through the prepass and one point light through the
+// lighting pass, then reads every target back. The plane covers the centre of the screen and
+// leaves the corners empty
+#[test]
+fn prepass_and_lighting_fill_the_targets() {
+    let (_events_loop, display) = match display() {
+        Some(display) => display,
+        None => {
+            // Written to stderr directly, as the test harness hides `eprintln!` of passing tests
+            let _ = writeln!(io::stderr(), "skipping G-buffer test: no OpenGL display available");
+            return;
+        }
+    };
+
+    let plane = VertexBuffer::new(&display, &gen_plane()).unwrap();
+    let white = texel(&display, (1.0, 1.0, 1.0, 1.0));
+    let flat_normal = texel(&display, (0.5, 0.5, 1.0, 1.0));
+    let no_depth = texel(&display, (0.0, 0.0, 0.0, 0.0));
+    let object = RenderObject::new(Identity, &plane, &white, &white, &flat_normal, &no_depth, 0.0);
+
+    let mut camera = PCamera::new_preset_perspective(
+        Vec3::new(0.0, 0.0, 3.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    );
+    camera.set_aspect(1.0);
+    let light = Light::point(Vec3::new(0.0, 0.0, 1.0), [1.0, 1.0, 1.0], 5.0);
+    let volumes = LightVolumes::generate(&display).unwrap();
+    let object_program = prepass_program(&display).unwrap();
+    let light_program = lighting_program(&display).unwrap();
+
+    let gbuffer = GBuffer::with_dimensions(&display, (SIZE, SIZE)).unwrap();
+    {
+        let mut buffers = FrameBuffers::new(&gbuffer).unwrap();
+        let mut prepass = buffers.begin_prepass();
+        prepass
+            .draw_object(&object, &camera, &object_program, &Default::default())
+            .unwrap();
+        let mut lighting = prepass.begin_lighting().unwrap();
+        lighting
+            .draw_light(32.0, &light, &volumes, &camera, &light_program, &Default::default())
+            .unwrap();
+        lighting.finish();
+    }
+
+    let (centre, corner) = (SIZE / 2, 0);
+
+    let diffuse = gbuffer.read_attachment("diffuse").unwrap();
+    assert_close(diffuse.pixel(centre, centre), &[1.0, 1.0, 1.0, 1.0]);
+    assert_close(diffuse.pixel(corner, corner), &[0.0, 0.0, 0.0, 0.0]);
+
+    let specular = gbuffer.read_attachment("specular").unwrap();
+    assert_close(specular.pixel(centre, centre), &[1.0, 1.0, 1.0, 1.0]);
+
+    // +z, mapped to 0..1
+    let normals = gbuffer.read_normals().unwrap();
+    assert_close(normals.pixel(centre, centre), &[0.5, 0.5, 1.0]);
+
+    // The plane is 3 units in front of the camera
+    let depth = gbuffer.read_linear_depth(&camera).unwrap();
+    assert_close(depth.pixel(centre, centre), &[3.0]);
+
+    // The light is in front of the plane, and the background stays unlit
+    let lit = gbuffer.read_light();
+    let centre_light = lit.pixel(centre, centre);
+    assert!(centre_light[..3].iter().all(|&channel| channel > 0.0), "unlit plane: {:?}", centre_light);
+    assert_close(lit.pixel(corner, corner), &[0.0, 0.0, 0.0, 0.0]);
+}
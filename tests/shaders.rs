@@ -0,0 +1,148 @@
+extern crate renderer;
+
+use renderer::preprocess::{Defines, Preprocessor, ShaderFiles};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const SHADER_DIR: &str = "src/shaders";
+
+// Compiles every shader under src/shaders with glslang, the Khronos reference front end, so type
+// errors and undeclared names are caught by `cargo test` instead of when the program is created.
+// Every combination of the `#ifdef` switches a shader uses is compiled. Files without a
+// `#version` are only meant to be included and are checked through the shaders that include them.
+// The validator is looked up on the PATH, or set GLSLANG_VALIDATOR to its location. Without either
+// the test is skipped with a notice, see the README
+#[test]
+fn every_shader_permutation_compiles() {
+    let validator = match env::var("GLSLANG_VALIDATOR") {
+        Ok(validator) => validator,
+        Err(_) => {
+            let found = Command::new("glslangValidator")
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok();
+            if !found {
+                // Written to stderr directly, as the test harness hides `eprintln!` of passing tests
+                let _ = writeln!(
+                    io::stderr(),
+                    "skipping shader validation: glslangValidator isn't on the PATH. Install \
+                     glslang (the glslang-tools package on most distributions) or set \
+                     GLSLANG_VALIDATOR"
+                );
+                return;
+            }
+            "glslangValidator".to_string()
+        }
+    };
+    let shader_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(SHADER_DIR);
+
+    let mut shaders = Vec::new();
+    collect_shaders(&shader_dir, &mut shaders);
+
+    let files = ShaderFiles::Directory(shader_dir.clone());
+    let mut errors = Vec::new();
+    let mut compiled = 0;
+
+    for path in &shaders {
+        let source = fs::read_to_string(path).expect("failed to read shader");
+        if !source.contains("#version") {
+            continue;
+        }
+
+        let name = path
+            .strip_prefix(&shader_dir)
+            .expect("shader outside of the shader directory")
+            .to_string_lossy()
+            .replace('\\', "/");
+        let stage = if path.file_stem().map_or(false, |stem| stem == "vertex") {
+            "vert"
+        } else {
+            "frag"
+        };
+
+        let mut preprocessor = Preprocessor::new(&files);
+        if let Err(err) = preprocessor.process(&name, &Defines::new()) {
+            errors.push(format!("{}: {}", name, err));
+            continue;
+        }
+        let conditions: Vec<String> = preprocessor.conditions().iter().cloned().collect();
+
+        for mask in 0..(1usize << conditions.len()) {
+            let defines: Defines = conditions
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| mask & (1 << i) != 0)
+                .map(|(_, name)| (name.clone(), String::new()))
+                .collect();
+
+            let mut preprocessor = Preprocessor::new(&files);
+            let result = preprocessor
+                .process(&name, &defines)
+                .map_err(|err| err.to_string())
+                .and_then(|output| validate(&validator, stage, &output));
+            compiled += 1;
+
+            if let Err(err) = result {
+                let defines: Vec<&str> = defines.keys().map(|k| k.as_str()).collect();
+                errors.push(format!("{} [{}]:\n{}", name, defines.join(", "), err));
+            }
+        }
+    }
+
+    assert!(compiled > 0, "no shaders found in {}", shader_dir.display());
+    if !errors.is_empty() {
+        panic!("invalid shaders:\n{}", errors.join("\n"));
+    }
+}
+
+// Runs the validator on the preprocessed source and returns its log if the source doesn't compile
+fn validate(validator: &str, stage: &str, source: &str) -> Result<(), String> {
+    let mut child = Command::new(validator)
+        .args(&["--stdin", "-S", stage])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|err| {
+            panic!("failed to run {} ({}), check GLSLANG_VALIDATOR", validator, err)
+        });
+
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(source.as_bytes())
+        .expect("failed to write to the validator");
+    let output = child.wait_with_output().expect("failed to wait for the validator");
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+fn collect_shaders(dir: &Path, shaders: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("failed to read shader directory")
+        .map(|entry| entry.expect("failed to read shader directory").path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_shaders(&path, shaders);
+        } else if path.extension().map_or(false, |ext| ext == "glsl") {
+            shaders.push(path);
+        }
+    }
+}